@@ -1,13 +1,15 @@
+use async_std::path::PathBuf;
 use async_std::sync::Arc;
 use futures::executor::block_on;
 use log::{trace, warn};
 use surf::http::Method;
 
-use chord::value::{json, Value};
+use chord::action::prelude::*;
+use chord::value::{from_str, json, Map, Value};
 use chord::Error;
 
 use crate::action::docker::engine::Engine;
-use crate::action::docker::image::Image;
+use crate::action::docker::task_path;
 
 pub struct Container {
     engine: Arc<Engine>,
@@ -15,20 +17,13 @@ pub struct Container {
 }
 
 impl Container {
-    pub async fn new(
-        docker: Arc<Engine>,
-        image: &Image,
-        name: &str,
-        cmd: Value,
-    ) -> Result<Container, Error> {
-        trace!("container create {}, {}", name, cmd);
+    pub async fn new(docker: Arc<Engine>, name: &str, body: Value) -> Result<Container, Error> {
+        trace!("container create {}, {}", name, body);
         docker
             .call(
                 format!("containers/create?name={}", name).as_str(),
                 Method::Post,
-                Some(json!({ "Image": image.name(),
-                                    "Cmd": cmd
-                })),
+                Some(body),
                 1,
             )
             .await
@@ -50,16 +45,22 @@ impl Container {
             .await
     }
 
-    pub async fn wait(&self) -> Result<Vec<String>, Error> {
+    /// the exit code
+    pub async fn wait(&self) -> Result<i64, Error> {
         trace!("container wait {}", self.name);
-        self.engine
+        let res = self
+            .engine
             .call(
                 format!("containers/{}/wait", self.name).as_str(),
                 Method::Post,
                 None,
                 1,
             )
-            .await
+            .await?;
+        let res: Value = from_str(res.join("").as_str())?;
+        res["StatusCode"]
+            .as_i64()
+            .ok_or(err!("104", format!("missing StatusCode: {}", res)))
     }
 
     pub async fn tail(&self, tail: usize) -> Result<Vec<String>, Error> {
@@ -92,3 +93,129 @@ impl Drop for Container {
             });
     }
 }
+
+/// the body of `containers/create`
+///
+/// `mounts` sources are paths `[<action dir>, <dir>...]` under the workdir owned by this task like `fstore`
+pub fn body_create(
+    image: &str,
+    args: &Value,
+    workdir: Option<&PathBuf>,
+    arg: &dyn RunArg,
+) -> Result<Value, Error> {
+    let mut body = Map::new();
+    body.insert("Image".into(), Value::String(image.into()));
+    if !args["cmd"].is_null() {
+        body.insert("Cmd".into(), args["cmd"].clone());
+    }
+    match &args["entrypoint"] {
+        Value::Null => {}
+        Value::String(e) => {
+            body.insert("Entrypoint".into(), json!([e]));
+        }
+        e => {
+            body.insert("Entrypoint".into(), e.clone());
+        }
+    }
+    if !args["env"].is_null() {
+        body.insert("Env".into(), env_create(&args["env"])?);
+    }
+    if let Some(user) = args["user"].as_str() {
+        body.insert("User".into(), Value::String(user.into()));
+    }
+    if let Some(dir) = args["workdir"].as_str() {
+        body.insert("WorkingDir".into(), Value::String(dir.into()));
+    }
+
+    let mut host = Map::new();
+    if let Some(network) = args["network"].as_str() {
+        host.insert("NetworkMode".into(), Value::String(network.into()));
+    }
+    if let Some(memory) = args["memory"].as_u64() {
+        host.insert("Memory".into(), json!(memory));
+    }
+    if let Some(cpus) = args["cpus"].as_f64() {
+        host.insert("NanoCpus".into(), json!((cpus * 1e9) as u64));
+    }
+
+    if let Some(ports) = args["ports"].as_object() {
+        let mut exposed = Map::new();
+        let mut bindings = Map::new();
+        for (port, host_port) in ports {
+            let port = if port.contains('/') {
+                port.clone()
+            } else {
+                format!("{}/tcp", port)
+            };
+            let host_port = match host_port {
+                Value::String(p) => p.clone(),
+                p => p.to_string(),
+            };
+            exposed.insert(port.clone(), json!({}));
+            bindings.insert(port, json!([{ "HostPort": host_port }]));
+        }
+        body.insert("ExposedPorts".into(), Value::Object(exposed));
+        host.insert("PortBindings".into(), Value::Object(bindings));
+    }
+
+    if let Some(mounts) = args["mounts"].as_array() {
+        let workdir = workdir.ok_or(err!("105", "missing workdir"))?;
+        let mut list = Vec::new();
+        for mount in mounts {
+            let source = mount_source(workdir.clone(), arg, &mount["source"])?;
+            let target = mount["target"]
+                .as_str()
+                .ok_or(err!("105", "missing mount target"))?;
+            list.push(json!({
+                "Type": "bind",
+                "Source": source.to_str().unwrap_or(""),
+                "Target": target,
+                "ReadOnly": mount["read_only"].as_bool().unwrap_or(false)
+            }));
+        }
+        host.insert("Mounts".into(), Value::Array(list));
+    }
+
+    if !host.is_empty() {
+        body.insert("HostConfig".into(), Value::Object(host));
+    }
+    Ok(Value::Object(body))
+}
+
+/// `{K: V}` or `["K=V"]`
+pub fn env_create(env: &Value) -> Result<Value, Error> {
+    match env {
+        Value::Object(map) => Ok(Value::Array(
+            map.iter()
+                .map(|(k, v)| {
+                    let v = v.as_str().map(str::to_owned).unwrap_or(v.to_string());
+                    Value::String(format!("{}={}", k, v))
+                })
+                .collect(),
+        )),
+        Value::Array(_) => Ok(env.clone()),
+        _ => Err(err!("105", "illegal env")),
+    }
+}
+
+fn mount_source(dir: PathBuf, arg: &dyn RunArg, path: &Value) -> Result<PathBuf, Error> {
+    let pav: Vec<&str> = path
+        .as_array()
+        .ok_or(err!("105", "missing mount source"))?
+        .iter()
+        .filter_map(|p| p.as_str())
+        .collect();
+
+    if pav.is_empty() {
+        return Err(err!("105", "missing mount source"));
+    }
+    task_path(dir, arg.id().case_id().task_id().to_string().as_str(), &pav)
+}
+
+#[test]
+fn env_create_test() {
+    assert_eq!(
+        json!(["A=1", "B=x"]),
+        env_create(&json!({"A": 1, "B": "x"})).unwrap()
+    );
+}
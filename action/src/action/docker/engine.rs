@@ -1,27 +1,46 @@
 use std::collections::VecDeque;
 use std::str::FromStr;
 
-use futures::AsyncBufReadExt;
-use log::trace;
+use futures::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt};
+use http_client::isahc::IsahcClient;
+use isahc::config::{Configurable, Dialer};
+use log::{debug, trace};
 use surf::http::headers::{HeaderName, HeaderValue};
 use surf::http::Method;
-use surf::{RequestBuilder, Response, Url};
+use surf::{Body, Client, RequestBuilder, Response, Url};
 
-use chord::value::Value;
+use chord::value::{from_str, Value};
 use chord::Error;
 use chord::{cause, err};
 
+/// `address` is `host:port`, or `unix:///var/run/docker.sock`
 pub struct Engine {
-    address: String,
+    client: Client,
+    base: String,
 }
 
 impl Engine {
     pub async fn new(address: String) -> Result<Engine, Error> {
+        let (base, builder) = match address.strip_prefix("unix://") {
+            Some(path) => (
+                "http://localhost".to_owned(),
+                isahc::HttpClient::builder().dial(Dialer::unix_socket(path)),
+            ),
+            None => (format!("http://{}", address), isahc::HttpClient::builder()),
+        };
+        let client = builder
+            .build()
+            .map_err(|e| err!("100", format!("docker client create fail: {}", e)))?;
+        let engine = Engine {
+            client: Client::with_http_client(IsahcClient::from_client(client)),
+            base,
+        };
+
         trace!("docker info {}", address);
-        call0(address.as_str(), "info", Method::Get, None, 999)
+        engine
+            .call("info", Method::Get, None, 999)
             .await
-            .map_err(|e| e.into())
-            .map(|_| Engine { address })
+            .map(|_| engine)
     }
 
     pub async fn call(
@@ -31,62 +50,197 @@ impl Engine {
         data: Option<Value>,
         tail_size: usize,
     ) -> Result<Vec<String>, Error> {
-        call0(self.address.as_str(), uri, method, data, tail_size)
-            .await
-            .map_err(|e| e.into())
+        let body = match data {
+            Some(d) => Some(Body::from_json(&d).map_err(|e| err!("103", e.to_string()))?),
+            None => None,
+        };
+        self.request(uri, method, vec![], body, tail_size).await
+    }
+
+    /// the last `tail_size` lines of the response,
+    /// an `error` in the json lines of a pull or a build fails it as well as the status
+    pub async fn request(
+        &self,
+        uri: &str,
+        method: Method,
+        headers: Vec<(&str, String)>,
+        body: Option<Body>,
+        tail_size: usize,
+    ) -> Result<Vec<String>, Error> {
+        call0(
+            &self.client,
+            self.base.as_str(),
+            uri,
+            method,
+            headers,
+            body,
+            tail_size,
+        )
+        .await
+        .map_err(|e| e.into())
     }
 }
 
 async fn call0(
-    address: &str,
+    client: &Client,
+    base: &str,
     uri: &str,
     method: Method,
-    data: Option<Value>,
+    headers: Vec<(&str, String)>,
+    body: Option<Body>,
     tail_size: usize,
 ) -> Result<Vec<String>, DockerError> {
-    let url = format!("http://{}/{}", address, uri);
+    let url = format!("{}/{}", base, uri);
     let url = Url::from_str(url.as_str()).or(Err(err!("100", format!("invalid url: {}", url))))?;
     let mut rb = RequestBuilder::new(method, url);
     rb = rb.header(
         HeaderName::from_str("Content-Type").unwrap(),
         HeaderValue::from_str("application/json")?,
     );
-    if let Some(d) = data {
-        rb = rb.body(d);
+    for (name, value) in headers {
+        rb = rb.header(
+            HeaderName::from_str(name)?,
+            HeaderValue::from_str(value.as_str())?,
+        );
+    }
+    if let Some(b) = body {
+        let mime = b.mime().clone();
+        rb = rb.body(b).content_type(mime);
     }
 
-    let mut res: Response = rb.send().await?;
+    let mut res: Response = client.send(rb.build()).await?;
+
+    // logs and exec output are multiplexed, each frame led by an 8 bytes header
+    let multiplexed = matches!(
+        res.content_type()
+            .map(|t| t.essence().to_string())
+            .as_deref(),
+        Some("application/octet-stream")
+            | Some("application/vnd.docker.raw-stream")
+            | Some("application/vnd.docker.multiplexed-stream")
+    );
 
-    let mut tail: VecDeque<String> = VecDeque::with_capacity(tail_size);
-    let mut line = String::new();
+    let mut tail = Tail {
+        lines: VecDeque::with_capacity(tail_size),
+        size: tail_size,
+    };
+    if multiplexed {
+        frame_read(&mut res, &mut tail).await?;
+    } else {
+        line_read(&mut res, &mut tail).await?;
+    }
+    if !res.status().is_success() {
+        Err(err!("101", res.status().to_string()))?
+    } else {
+        Ok(tail.lines.into())
+    }
+}
+
+struct Tail {
+    lines: VecDeque<String>,
+    size: usize,
+}
+
+impl Tail {
+    fn push(&mut self, line: String) -> Result<(), Error> {
+        trace!("{}", line);
+        progress(line.as_str())?;
+
+        self.lines.push_back(line);
+        if self.lines.len() > self.size {
+            self.lines.pop_front();
+        }
+        Ok(())
+    }
+}
+
+async fn line_read(reader: &mut (impl AsyncBufRead + Unpin), tail: &mut Tail) -> Result<(), Error> {
     loop {
-        line.clear();
-        let size = res
+        let mut line = String::new();
+        let size = reader
             .read_line(&mut line)
             .await
-            .or_else(|e| Err(cause!("docker", "read fail", e)))?;
-        if size > 0 {
-            if res.content_type().is_some()
-                && res.content_type().unwrap().to_string() == "application/octet-stream"
-            {
-                line = String::from_utf8_lossy(&line.as_bytes()[8..]).to_string();
-            }
-
-            trace!("{}", line);
-
-            tail.push_back(line.clone());
-            if tail.len() > tail_size {
-                tail.pop_front();
-            }
-        } else {
-            break;
+            .map_err(|e| cause!("docker", "read fail", e))?;
+        if size == 0 {
+            return Ok(());
         }
+        tail.push(line)?;
     }
-    return if !res.status().is_success() {
-        Err(err!("101", res.status().to_string()))?
-    } else {
-        Ok(tail.into())
+}
+
+/// frames of an 8 bytes header `[stream, 0, 0, 0, size: u32 be]` and `size` bytes of payload,
+/// a line of stdout or stderr may span frames
+async fn frame_read(reader: &mut (impl AsyncRead + Unpin), tail: &mut Tail) -> Result<(), Error> {
+    let mut pending: [Vec<u8>; 3] = Default::default();
+    let mut header = [0u8; 8];
+    while header_read(reader, &mut header).await? {
+        if header[0] > 2 || header[1..4] != [0, 0, 0] {
+            return Err(err!("docker", "invalid frame header"));
+        }
+        let size = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let mut payload = vec![0u8; size];
+        reader
+            .read_exact(&mut payload)
+            .await
+            .map_err(|e| cause!("docker", "read fail", e))?;
+
+        let buf = &mut pending[(header[0] as usize).min(2)];
+        buf.extend_from_slice(&payload);
+        while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=i).collect();
+            tail.push(String::from_utf8_lossy(&line).to_string())?;
+        }
+    }
+    for buf in pending.iter().filter(|b| !b.is_empty()) {
+        tail.push(String::from_utf8_lossy(buf).to_string())?;
+    }
+    Ok(())
+}
+
+/// false at the end of the stream
+async fn header_read(
+    reader: &mut (impl AsyncRead + Unpin),
+    header: &mut [u8; 8],
+) -> Result<bool, Error> {
+    let mut n = 0;
+    while n < header.len() {
+        let size = reader
+            .read(&mut header[n..])
+            .await
+            .map_err(|e| cause!("docker", "read fail", e))?;
+        if size == 0 {
+            return if n == 0 {
+                Ok(false)
+            } else {
+                Err(err!("docker", "truncated frame header"))
+            };
+        }
+        n += size;
+    }
+    Ok(true)
+}
+
+/// logs the status lines of a pull and the output of a build
+fn progress(line: &str) -> Result<(), Error> {
+    let value: Value = match from_str(line) {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
     };
+    if let Some(error) = value["error"].as_str() {
+        return Err(err!("104", error.to_string()));
+    }
+    if let Some(status) = value["status"].as_str() {
+        // bars of a layer download are left to trace
+        if value["progressDetail"]["current"].is_null() {
+            debug!("{} {}", value["id"].as_str().unwrap_or_default(), status);
+        }
+    } else if let Some(stream) = value["stream"].as_str() {
+        let stream = stream.trim();
+        if !stream.is_empty() {
+            debug!("{}", stream);
+        }
+    }
+    Ok(())
 }
 
 struct DockerError(chord::Error);
@@ -109,8 +263,53 @@ impl From<chord::value::Error> for DockerError {
     }
 }
 
-impl Into<chord::Error> for DockerError {
-    fn into(self) -> Error {
-        self.0
+impl From<DockerError> for chord::Error {
+    fn from(err: DockerError) -> Error {
+        err.0
+    }
+}
+
+#[test]
+fn frame_read_test() {
+    fn frame(stream: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
     }
+
+    let mut data = frame(1, b"hello\nwor");
+    data.extend(frame(2, b"oops\n"));
+    data.extend(frame(1, b"ld\n"));
+    data.extend(frame(1, &vec![b'x'; 70000]));
+    data.extend(frame(1, b"\nend"));
+
+    let mut tail = Tail {
+        lines: VecDeque::new(),
+        size: 10,
+    };
+    async_std::task::block_on(frame_read(&mut data.as_slice(), &mut tail)).unwrap();
+    let lines: Vec<String> = tail.lines.into();
+    assert_eq!(5, lines.len());
+    assert_eq!("hello\n", lines[0]);
+    assert_eq!("oops\n", lines[1]);
+    assert_eq!("world\n", lines[2]);
+    assert_eq!(70001, lines[3].len());
+    assert_eq!("end", lines[4]);
+
+    let mut tail = Tail {
+        lines: VecDeque::new(),
+        size: 10,
+    };
+    let truncated = &frame(1, b"hello")[..10];
+    let e = async_std::task::block_on(frame_read(&mut &truncated[..], &mut tail)).unwrap_err();
+    assert_eq!("docker", e.code());
+
+    let mut tail = Tail {
+        lines: VecDeque::new(),
+        size: 10,
+    };
+    let raw = b"not a frame\n";
+    let e = async_std::task::block_on(frame_read(&mut &raw[..], &mut tail)).unwrap_err();
+    assert_eq!("docker", e.code());
 }
@@ -0,0 +1,82 @@
+use async_std::sync::Arc;
+use log::trace;
+use surf::http::Method;
+
+use chord::action::prelude::*;
+use chord::value::from_str;
+
+use crate::action::docker::container::env_create;
+use crate::action::docker::engine::Engine;
+use crate::action::docker::image::log_value;
+
+/// runs a command in a container started by an earlier step, usually a detached one in `pre`
+pub struct ContainerExec {
+    engine: Arc<Engine>,
+}
+
+impl ContainerExec {
+    pub fn new(engine: Arc<Engine>) -> ContainerExec {
+        ContainerExec { engine }
+    }
+}
+
+#[async_trait]
+impl Action for ContainerExec {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let exec = &arg.args()["exec"];
+        let container = exec["container"]
+            .as_str()
+            .ok_or(err!("010", "missing container"))?;
+
+        let mut body = json!({
+            "AttachStdout": true,
+            "AttachStderr": true,
+            "Cmd": exec["cmd"]
+        });
+        if !exec["env"].is_null() {
+            body["Env"] = env_create(&exec["env"])?;
+        }
+        if let Some(user) = exec["user"].as_str() {
+            body["User"] = Value::String(user.into());
+        }
+        if let Some(dir) = exec["workdir"].as_str() {
+            body["WorkingDir"] = Value::String(dir.into());
+        }
+
+        trace!("container exec {}, {}", container, body);
+        let res = self
+            .engine
+            .call(
+                format!("containers/{}/exec", container).as_str(),
+                Method::Post,
+                Some(body),
+                999,
+            )
+            .await?;
+        let res: Value = from_str(res.join("").as_str())?;
+        let id = res["Id"]
+            .as_str()
+            .ok_or(err!("104", format!("missing Id: {}", res)))?;
+
+        let tail = arg.args()["tail"].as_u64().unwrap_or(1) as usize;
+        let log = self
+            .engine
+            .call(
+                format!("exec/{}/start", id).as_str(),
+                Method::Post,
+                Some(json!({ "Detach": false, "Tty": false })),
+                tail,
+            )
+            .await?;
+
+        let res = self
+            .engine
+            .call(format!("exec/{}/json", id).as_str(), Method::Get, None, 999)
+            .await?;
+        let res: Value = from_str(res.join("").as_str())?;
+        let code = res["ExitCode"].as_i64().unwrap_or(-1);
+
+        let value = log_value(log, code)?;
+        Ok(Box::new(value))
+    }
+}
@@ -1,49 +1,53 @@
+use async_std::path::PathBuf;
 use async_std::sync::Arc;
+use futures::channel::oneshot;
 use futures::executor::block_on;
 use log::{trace, warn};
 use surf::http::Method;
+use surf::{Body, Url};
 
 use chord::action::prelude::*;
 use chord::value::from_str;
 use chord::Error;
 
-use crate::action::docker::container::Container;
+use crate::action::docker::container::{body_create, Container};
 use crate::action::docker::engine::Engine;
 
 pub struct Image {
     engine: Arc<Engine>,
     name: String,
+    workdir: Option<PathBuf>,
+    /// pulled or built by this run, so removed with it
+    created: bool,
 }
 
 #[async_trait]
 impl Action for Image {
     async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
-        let cmd = &arg.args()["cmd"];
-
-        let mut container = Container::new(
-            self.engine.clone(),
-            &self,
-            arg.id().to_string().as_str(),
-            cmd.clone(),
-        )
-        .await?;
+        let args = arg.args();
+        let name = match args["name"].as_str() {
+            Some(name) => name.to_owned(),
+            None => arg.id().to_string(),
+        };
+        let body = body_create(self.name(), args, self.workdir.as_ref(), arg)?;
+
+        let mut container = Container::new(self.engine.clone(), name.as_str(), body).await?;
         container.start().await?;
-        container.wait().await?;
 
-        let tail = arg.args()["tail"].as_u64().unwrap_or(1) as usize;
-        let tail_log = container.tail(tail).await?;
-        let tail_log: Vec<String> = tail_log
-            .into_iter()
-            .map(|row| row.trim().to_string())
-            .filter(|row| !row.is_empty())
-            .collect();
-
-        if tail_log.len() > 0 {
-            let value: Value = from_str(tail_log.join("").as_str())?;
-            Ok(Box::new(value))
-        } else {
-            Ok(Box::new(Value::Null))
+        if args["detach"].as_bool().unwrap_or(false) {
+            // removed when the task ends
+            arg.task_session().set_resource(
+                format!("docker:{}", name).as_str(),
+                Some(Arc::new(container)),
+            );
+            return Ok(Box::new(json!({ "name": name })));
         }
+
+        let code = container.wait().await?;
+        let tail = args["tail"].as_u64().unwrap_or(1) as usize;
+        let tail_log = container.tail(tail).await?;
+        let value = log_value(tail_log, code)?;
+        Ok(Box::new(value))
     }
 }
 
@@ -52,33 +56,114 @@ impl Image {
         self.name.as_str()
     }
 
-    pub async fn new(engine: Arc<Engine>, name: &str) -> Result<Image, Error> {
-        let name = if name.contains(":") {
-            name.into()
-        } else {
-            format!("{}:latest", name)
-        };
+    /// `pull` is `missing`, `always` or `never`, `auth` is sent to the registry as is
+    pub async fn new(
+        engine: Arc<Engine>,
+        name: &str,
+        pull: &str,
+        auth: Option<&Value>,
+        workdir: Option<PathBuf>,
+    ) -> Result<Image, Error> {
+        let name = image_name(name);
 
-        trace!("image create {}", name);
-        engine
+        let exists = engine
             .call(
-                format!("images/create?fromImage={}", name).as_str(),
-                Method::Post,
+                format!("images/{}/json", name).as_str(),
+                Method::Get,
                 None,
                 1,
             )
             .await
-            .map(|_| Image { engine, name })
+            .is_ok();
+        let pull = match pull {
+            "always" => true,
+            "missing" => !exists,
+            "never" if exists => false,
+            "never" => return Err(err!("108", format!("image not found {}", name))),
+            p => return Err(err!("108", format!("unsupported pull {}", p))),
+        };
+
+        if pull {
+            trace!("image pull {}", name);
+            let headers = match auth {
+                Some(auth) => vec![(
+                    "X-Registry-Auth",
+                    base64::encode_config(auth.to_string(), base64::URL_SAFE),
+                )],
+                None => vec![],
+            };
+            engine
+                .request(
+                    format!("images/create?fromImage={}", name).as_str(),
+                    Method::Post,
+                    headers,
+                    None,
+                    1,
+                )
+                .await?;
+        }
+
+        Ok(Image {
+            engine,
+            name,
+            workdir,
+            created: pull && !exists,
+        })
+    }
+
+    /// builds the `dockerfile` in the `context` dir as `tag`
+    pub async fn build(
+        engine: Arc<Engine>,
+        tag: &str,
+        context: PathBuf,
+        dockerfile: &str,
+        build_args: &Value,
+        workdir: Option<PathBuf>,
+    ) -> Result<Image, Error> {
+        let name = image_name(tag);
+
+        let mut url = Url::parse("http://localhost/build")?;
+        url.query_pairs_mut()
+            .append_pair("t", name.as_str())
+            .append_pair("dockerfile", dockerfile)
+            .append_pair("rm", "true");
+        if build_args.is_object() {
+            url.query_pairs_mut()
+                .append_pair("buildargs", build_args.to_string().as_str());
+        }
+
+        trace!("image build {}, {:?}", name, context);
+        let mut body = Body::from_bytes(context_tar(context).await?);
+        body.set_mime("application/x-tar");
+        engine
+            .request(
+                format!("build?{}", url.query().unwrap_or_default()).as_str(),
+                Method::Post,
+                vec![],
+                Some(body),
+                1,
+            )
+            .await?;
+
+        Ok(Image {
+            engine,
+            name,
+            workdir,
+            created: true,
+        })
     }
 }
 
 impl Drop for Image {
     fn drop(&mut self) {
+        if !self.created {
+            return;
+        }
         let uri = format!("images/{}", self.name);
         let f = self.engine.call(uri.as_str(), Method::Delete, None, 1);
         let _ = block_on(f)
             .map_err(|e| {
-                if e.code() == "docker" && e.message() == "404" {
+                if e.code() == "101" && e.message() == "404" {
                     trace!("image not found {}", self.name);
                 } else {
                     warn!("image remove fail {}, {}", self.name, e);
@@ -89,3 +174,72 @@ impl Drop for Image {
             });
     }
 }
+
+fn image_name(name: &str) -> String {
+    // a registry port is not a tag
+    if name.rsplit('/').next().unwrap_or(name).contains(':') {
+        name.into()
+    } else {
+        format!("{}:latest", name)
+    }
+}
+
+/// archives the build context off the executor
+async fn context_tar(context: PathBuf) -> Result<Vec<u8>, Error> {
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let mut builder = tar::Builder::new(Vec::new());
+        let res = builder
+            .append_dir_all(".", context.as_path())
+            .and_then(|_| builder.into_inner());
+        let _ = tx.send(res);
+    });
+    rx.await
+        .map_err(|_| err!("109", "build context archive fail"))?
+        .map_err(|e| err!("109", format!("build context archive fail: {}", e)))
+}
+
+/// the log tail as json, or as text when it is not,
+/// a non-zero exit code is an error carrying the log
+pub fn log_value(tail_log: Vec<String>, code: i64) -> Result<Value, Error> {
+    let tail_log: Vec<String> = tail_log
+        .into_iter()
+        .map(|row| row.trim().to_string())
+        .filter(|row| !row.is_empty())
+        .collect();
+
+    if code != 0 {
+        return Err(err!(
+            "107",
+            format!("exit {}: {}", code, tail_log.join("\n"))
+        ));
+    }
+    if tail_log.is_empty() {
+        return Ok(Value::Null);
+    }
+    let log = tail_log.join("");
+    Ok(from_str(log.as_str()).unwrap_or(Value::String(tail_log.join("\n"))))
+}
+
+#[test]
+fn log_value_test() {
+    assert_eq!(
+        json!({"size": 1}),
+        log_value(vec!["{\"size\":".into(), " 1}\n".into()], 0).unwrap()
+    );
+    assert_eq!(
+        json!("a\nb"),
+        log_value(vec!["a".into(), "b".into()], 0).unwrap()
+    );
+    assert_eq!("107", log_value(vec!["boom".into()], 2).unwrap_err().code());
+}
+
+#[test]
+fn image_name_test() {
+    assert_eq!("busybox:latest", image_name("busybox"));
+    assert_eq!("busybox:1.36", image_name("busybox:1.36"));
+    assert_eq!(
+        "localhost:5000/app:latest",
+        image_name("localhost:5000/app")
+    );
+}
@@ -1,36 +1,220 @@
+use std::str::FromStr;
+
 use crate::action::docker::engine::Engine;
+use crate::action::workdir::check_path;
+use async_std::path::PathBuf;
 use async_std::sync::Arc;
 use chord::action::prelude::*;
+use exec::ContainerExec;
 use image::Image;
 
 mod container;
 mod engine;
+mod exec;
 mod image;
 
 pub struct Docker {
     engine: Arc<Engine>,
+    workdir: Option<PathBuf>,
+    registry: Value,
 }
 
 impl Docker {
     pub async fn new(conf: Option<Value>) -> Result<Docker, Error> {
-        let address: String = conf.map_or("".into(), |v| {
+        let address: String = conf.as_ref().map_or("".into(), |v| {
             v["address"].as_str().unwrap_or("127.0.0.1:2375").into()
         });
+        let workdir = match conf.as_ref().and_then(|c| c["workdir"].as_str()) {
+            Some(w) => Some(PathBuf::from_str(w)?),
+            None => None,
+        };
+        let registry = conf.as_ref().map_or(Value::Null, |c| c["registry"].clone());
         Ok(Docker {
             engine: Arc::new(Engine::new(address).await?),
+            workdir,
+            registry,
         })
     }
 }
 
+/// ```yaml
+/// docker:
+///     address: unix:///var/run/docker.sock
+///     workdir: /data/chord/work
+///     registry:
+///         registry.example.com:
+///             username: chord
+///             password: secret
+/// ```
+///
+/// the config, `address` is `host:port` or a unix socket, `registry` credentials are by host
+///
+/// ```yaml
+/// image: "curlimages/curl"
+/// pull: missing
+/// auth: { "username": "chord", "password": "secret" }
+/// cmd: ["curl", "-s", "http://mock:8080/health"]
+/// entrypoint: ["/bin/sh", "-c"]
+/// env: { "LANG": "C" }
+/// user: "1000"
+/// workdir: /data
+/// network: test
+/// ports: { "8080": 18080 }
+/// mounts:
+///     - source: ["{{step.download.value.path.0}}"]
+///       target: /data
+///       read_only: true
+/// memory: 268435456
+/// cpus: 0.5
+/// name: mock
+/// detach: false
+/// tail: 1
+/// ```
+///
+/// runs a container of `image` until it exits, the value is the log tail, as json when it is,
+/// a non-zero exit code fails the step with the log.
+/// `mounts` need `workdir` in the config, sources are paths owned by this task like `fstore`.
+/// `detach: true` leaves the container `name` running until the task ends, so a `pre` step
+/// can start one that later steps run commands in.
+/// `pull` is `missing`, `always` or `never`, only an image pulled by this run is removed after it
+///
+/// ```yaml
+/// build:
+///     context: ["{{pre.step.dockerfile.value.path.0}}"]
+///     dockerfile: Dockerfile
+///     args: { "VERSION": "1.0" }
+///     tag: mock:test
+/// cmd: ["/mock"]
+/// ```
+///
+/// builds `dockerfile` in a `context` dir owned by this task instead, images are made when
+/// the task starts so the context is written by a `pre` step, the image is removed after the run
+///
+/// ```yaml
+/// exec:
+///     container: mock
+///     cmd: ["cat", "/data/request.log"]
+///     env: { "LANG": "C" }
+///     user: root
+///     workdir: /data
+/// tail: 10
+/// ```
 #[async_trait]
 impl Factory for Docker {
     async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
-        let image = arg.args()["image"]
-            .as_str()
-            .ok_or(err!("010", "missing image"))?;
+        if arg.args()["exec"].is_object() {
+            return Ok(Box::new(ContainerExec::new(self.engine.clone())));
+        }
+
+        let args = arg.args();
+        if args["build"].is_object() {
+            return Ok(Box::new(self.build(arg, &args["build"]).await?));
+        }
+
+        let image = args["image"].as_str().ok_or(err!("010", "missing image"))?;
+        let auth = match &args["auth"] {
+            Value::Null => registry_auth(&self.registry, image),
+            auth => Some(auth.clone()),
+        };
+        let pull = args["pull"].as_str().unwrap_or("missing");
 
-        let image = Image::new(self.engine.clone(), image).await?;
+        let image = Image::new(
+            self.engine.clone(),
+            image,
+            pull,
+            auth.as_ref(),
+            self.workdir.clone(),
+        )
+        .await?;
 
         Ok(Box::new(image))
     }
 }
+
+impl Docker {
+    async fn build(&self, arg: &dyn CreateArg, build: &Value) -> Result<Image, Error> {
+        let workdir = self.workdir.clone().ok_or(err!("105", "missing workdir"))?;
+        let mut context = Vec::new();
+        for p in build["context"]
+            .as_array()
+            .ok_or(err!("010", "missing build context"))?
+        {
+            let p = p.as_str().ok_or(err!("010", "missing build context"))?;
+            context.push(arg.render_str(p)?);
+        }
+        let context: Vec<&str> = context.iter().map(|p| p.as_str()).collect();
+        if context.is_empty() {
+            return Err(err!("010", "missing build context"));
+        }
+        let context = task_path(workdir, arg.id().task_id().to_string().as_str(), &context)?;
+
+        let tag = match build["tag"].as_str() {
+            Some(tag) => arg.render_str(tag)?,
+            None => image_tag(arg.id().to_string().as_str()),
+        };
+        Image::build(
+            self.engine.clone(),
+            tag.as_str(),
+            context,
+            build["dockerfile"].as_str().unwrap_or("Dockerfile"),
+            &build["args"],
+            self.workdir.clone(),
+        )
+        .await
+    }
+}
+
+/// `[<action dir>, <dir>...]` under `dir`, owned by this task like `fstore`
+pub fn task_path(mut dir: PathBuf, task_id: &str, path: &[&str]) -> Result<PathBuf, Error> {
+    if !check_path(task_id, path) {
+        return Err(err!("106", "forbidden access"));
+    }
+    for pa in path {
+        dir = dir.join(pa);
+    }
+    Ok(dir)
+}
+
+/// the credentials of the registry `image` is in, docker hub when it names none
+fn registry_auth(registry: &Value, image: &str) -> Option<Value> {
+    let host = match image.split_once('/') {
+        Some((host, _)) if host.contains('.') || host.contains(':') || host == "localhost" => host,
+        _ => "docker.io",
+    };
+    let cred = registry[host].as_object()?;
+    Some(json!({
+        "username": cred.get("username").cloned().unwrap_or(Value::Null),
+        "password": cred.get("password").cloned().unwrap_or(Value::Null),
+        "serveraddress": host
+    }))
+}
+
+/// repository names are lowercase `[a-z0-9._-]`
+fn image_tag(id: &str) -> String {
+    let name: String = id
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '.' | '_' | '-' => c,
+            _ => '-',
+        })
+        .collect();
+    format!("chord-{}:latest", name)
+}
+
+#[test]
+fn registry_auth_test() {
+    let registry = json!({
+        "docker.io": {"username": "hub", "password": "p"},
+        "localhost:5000": {"username": "local", "password": "q"}
+    });
+    assert_eq!(
+        "hub",
+        registry_auth(&registry, "busybox").unwrap()["username"]
+    );
+    assert_eq!(
+        "local",
+        registry_auth(&registry, "localhost:5000/app").unwrap()["username"]
+    );
+    assert!(registry_auth(&registry, "quay.io/app").is_none());
+}
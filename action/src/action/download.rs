@@ -6,13 +6,17 @@ use futures::executor::block_on;
 use log::{trace, warn};
 use surf::http::headers::{HeaderName, HeaderValue};
 use surf::http::Method;
-use surf::{RequestBuilder, Response, Url};
+use surf::{Client, RequestBuilder, Response, Url};
 
 use chord::action::prelude::*;
 use chord::value::{Map, Number};
 
+use crate::action::http::client::ClientPool;
+use crate::action::http::cookie;
+
 pub struct DownloadFactory {
     workdir: PathBuf,
+    pool: ClientPool,
 }
 
 impl DownloadFactory {
@@ -34,19 +38,26 @@ impl DownloadFactory {
 
         async_std::fs::create_dir_all(workdir.as_path()).await?;
 
-        Ok(DownloadFactory { workdir })
+        let pool = ClientPool::new(Some(config))?;
+
+        Ok(DownloadFactory { workdir, pool })
     }
 }
 
 #[async_trait]
 impl Factory for DownloadFactory {
     async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let client = match arg.args()["client"].as_str() {
+            Some(name) => self.pool.get(Some(arg.render_str(name)?.as_str()))?,
+            None => self.pool.get(None)?,
+        };
         let tmp = self.workdir.join(arg.id().to_string());
         async_std::fs::create_dir_all(tmp.as_path()).await?;
         trace!("tmp create {}", tmp.as_path().to_str().unwrap());
         Ok(Box::new(Download {
             name: arg.id().to_string(),
             tmp,
+            client,
         }))
     }
 }
@@ -54,6 +65,7 @@ impl Factory for DownloadFactory {
 struct Download {
     name: String,
     tmp: PathBuf,
+    client: Client,
 }
 
 #[async_trait]
@@ -72,7 +84,7 @@ async fn run0(
     let url = args["url"].as_str().ok_or(err!("102", "missing url"))?;
     let url = Url::from_str(url).or(Err(err!("103", format!("invalid url: {}", url))))?;
 
-    let mut rb = RequestBuilder::new(Method::Get, url);
+    let mut rb = RequestBuilder::new(Method::Get, url.clone());
     if let Some(header) = args["header"].as_object() {
         for (k, v) in header.iter() {
             let hn =
@@ -110,7 +122,15 @@ async fn run0(
         .await?;
     let writer = BufWriter::new(file);
 
-    let mut res: Response = rb.send().await?;
+    let session = cookie::session(arg)?;
+    let mut req = rb.build();
+    cookie::apply(session, &mut req, &url);
+
+    let mut res: Response = download.client.send(req).await?;
+    if let Some(s) = session {
+        cookie::store(s, &url, &res);
+    }
+
     let mut value = Map::new();
     value.insert(
         String::from("status"),
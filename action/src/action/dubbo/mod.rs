@@ -1,7 +1,8 @@
 use chord::action::prelude::*;
 
 mod gateway;
-// mod telnet;
+mod native;
+mod telnet;
 
 pub struct DubboFactory {
     delegate: Box<dyn Factory>,
@@ -24,9 +25,12 @@ impl DubboFactory {
             "gateway" => Ok(DubboFactory {
                 delegate: Box::new(gateway::DubboFactory::new(config).await?),
             }),
-            // "telnet" => Ok(DubboFactory {
-            //     delegate: Box::new(telnet::DubboFactory::new(config).await?),
-            // }),
+            "native" => Ok(DubboFactory {
+                delegate: Box::new(native::DubboFactory::new(config).await?),
+            }),
+            "telnet" => Ok(DubboFactory {
+                delegate: Box::new(telnet::DubboFactory::new(config).await?),
+            }),
             _ => Err(err!("102", "unsupported mode")),
         }
     }
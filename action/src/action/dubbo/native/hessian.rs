@@ -0,0 +1,536 @@
+//! the subset of hessian 2.0 needed by generic invocation, mapped to and from json values
+
+use chord::action::prelude::*;
+use chord::value::{Map, Number};
+
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder { buf: vec![] }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn null(&mut self) {
+        self.buf.push(b'N');
+    }
+
+    pub fn int(&mut self, v: i32) {
+        match v {
+            -16..=47 => self.buf.push((0x90 + v) as u8),
+            -2048..=2047 => {
+                self.buf.push((0xc8 + (v >> 8)) as u8);
+                self.buf.push(v as u8);
+            }
+            -262144..=262143 => {
+                self.buf.push((0xd4 + (v >> 16)) as u8);
+                self.buf.push((v >> 8) as u8);
+                self.buf.push(v as u8);
+            }
+            _ => {
+                self.buf.push(b'I');
+                self.buf.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn long(&mut self, v: i64) {
+        match v {
+            -8..=15 => self.buf.push((0xe0 + v) as u8),
+            -2048..=2047 => {
+                self.buf.push((0xf8 + (v >> 8)) as u8);
+                self.buf.push(v as u8);
+            }
+            -262144..=262143 => {
+                self.buf.push((0x3c + (v >> 16)) as u8);
+                self.buf.push((v >> 8) as u8);
+                self.buf.push(v as u8);
+            }
+            _ if v >= i32::MIN as i64 && v <= i32::MAX as i64 => {
+                self.buf.push(0x59);
+                self.buf.extend_from_slice(&(v as i32).to_be_bytes());
+            }
+            _ => {
+                self.buf.push(b'L');
+                self.buf.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn double(&mut self, v: f64) {
+        self.buf.push(b'D');
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    /// lengths count utf-16 units, each unit is written as utf-8 on its own, as java does
+    pub fn string(&mut self, v: &str) {
+        let units: Vec<u16> = v.encode_utf16().collect();
+        let mut chunks = units.chunks(0x8000).peekable();
+        if units.is_empty() {
+            self.buf.push(0);
+            return;
+        }
+        while let Some(chunk) = chunks.next() {
+            let len = chunk.len();
+            if chunks.peek().is_some() {
+                self.buf.push(b'R');
+                self.buf.extend_from_slice(&(len as u16).to_be_bytes());
+            } else if len < 32 {
+                self.buf.push(len as u8);
+            } else if len < 1024 {
+                self.buf.push((0x30 + (len >> 8)) as u8);
+                self.buf.push(len as u8);
+            } else {
+                self.buf.push(b'S');
+                self.buf.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            for u in chunk {
+                let u = *u as u32;
+                if u < 0x80 {
+                    self.buf.push(u as u8);
+                } else if u < 0x800 {
+                    self.buf.push((0xc0 | (u >> 6)) as u8);
+                    self.buf.push((0x80 | (u & 0x3f)) as u8);
+                } else {
+                    self.buf.push((0xe0 | (u >> 12)) as u8);
+                    self.buf.push((0x80 | ((u >> 6) & 0x3f)) as u8);
+                    self.buf.push((0x80 | (u & 0x3f)) as u8);
+                }
+            }
+        }
+    }
+
+    /// fixed length typed list, `ty` such as `[string`
+    pub fn list_begin(&mut self, len: usize, ty: &str) {
+        if len < 8 {
+            self.buf.push(0x70 + len as u8);
+            self.string(ty);
+        } else {
+            self.buf.push(b'V');
+            self.string(ty);
+            self.int(len as i32);
+        }
+    }
+
+    pub fn map_begin(&mut self) {
+        self.buf.push(b'H');
+    }
+
+    pub fn end(&mut self) {
+        self.buf.push(b'Z');
+    }
+
+    pub fn value(&mut self, v: &Value) {
+        match v {
+            Value::Null => self.null(),
+            Value::Bool(b) => self.buf.push(if *b { b'T' } else { b'F' }),
+            Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    if i >= i32::MIN as i64 && i <= i32::MAX as i64 {
+                        self.int(i as i32)
+                    } else {
+                        self.long(i)
+                    }
+                } else {
+                    self.double(n.as_f64().unwrap_or(0.0))
+                }
+            }
+            Value::String(s) => self.string(s),
+            Value::Array(a) => {
+                if a.len() < 8 {
+                    self.buf.push(0x78 + a.len() as u8);
+                } else {
+                    self.buf.push(0x58);
+                    self.int(a.len() as i32);
+                }
+                for e in a {
+                    self.value(e);
+                }
+            }
+            Value::Object(o) => {
+                self.map_begin();
+                for (k, v) in o {
+                    self.string(k);
+                    self.value(v);
+                }
+                self.end();
+            }
+        }
+    }
+}
+
+const DEPTH_MAX: usize = 128;
+
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    /// class name and field names of every class definition
+    classes: Vec<Vec<String>>,
+    types: Vec<String>,
+    refs: Vec<Value>,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Decoder<'a> {
+        Decoder {
+            buf,
+            pos: 0,
+            classes: vec![],
+            types: vec![],
+            refs: vec![],
+            depth: 0,
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        let b = *self
+            .buf
+            .get(self.pos)
+            .ok_or(err!("hessian", "unexpected end"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.pos + len > self.buf.len() {
+            return Err(err!("hessian", "unexpected end"));
+        }
+        let b = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(b)
+    }
+
+    fn peek(&self) -> Result<u8, Error> {
+        self.buf
+            .get(self.pos)
+            .copied()
+            .ok_or(err!("hessian", "unexpected end"))
+    }
+
+    fn u16(&mut self) -> Result<usize, Error> {
+        let b = self.bytes(2)?;
+        Ok(((b[0] as usize) << 8) | b[1] as usize)
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        let b = self.bytes(4)?;
+        Ok(i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        let b = self.bytes(8)?;
+        let mut a = [0u8; 8];
+        a.copy_from_slice(b);
+        Ok(i64::from_be_bytes(a))
+    }
+
+    pub fn int(&mut self) -> Result<i32, Error> {
+        match self.value()? {
+            Value::Number(n) => n
+                .as_i64()
+                .map(|i| i as i32)
+                .ok_or(err!("hessian", "expect int")),
+            _ => Err(err!("hessian", "expect int")),
+        }
+    }
+
+    pub fn string(&mut self) -> Result<String, Error> {
+        match self.value()? {
+            Value::String(s) => Ok(s),
+            Value::Null => Ok(String::new()),
+            _ => Err(err!("hessian", "expect string")),
+        }
+    }
+
+    fn utf16_units(&mut self, len: usize, units: &mut Vec<u16>) -> Result<(), Error> {
+        for _ in 0..len {
+            let b = self.byte()? as u32;
+            let u = if b < 0x80 {
+                b
+            } else if b & 0xe0 == 0xc0 {
+                ((b & 0x1f) << 6) | (self.byte()? as u32 & 0x3f)
+            } else if b & 0xf0 == 0xe0 {
+                let b1 = self.byte()? as u32;
+                let b2 = self.byte()? as u32;
+                ((b & 0x0f) << 12) | ((b1 & 0x3f) << 6) | (b2 & 0x3f)
+            } else {
+                return Err(err!("hessian", "invalid utf-8"));
+            };
+            units.push(u as u16);
+        }
+        Ok(())
+    }
+
+    /// string chunks starting at the current tag
+    fn string_chunks(&mut self) -> Result<String, Error> {
+        let mut units = vec![];
+        loop {
+            let tag = self.byte()?;
+            let (len, last) = match tag {
+                0x00..=0x1f => (tag as usize, true),
+                0x30..=0x33 => ((((tag - 0x30) as usize) << 8) | self.byte()? as usize, true),
+                b'S' => (self.u16()?, true),
+                b'R' => (self.u16()?, false),
+                _ => return Err(err!("hessian", format!("invalid string tag {:#x}", tag))),
+            };
+            self.utf16_units(len, &mut units)?;
+            if last {
+                return Ok(String::from_utf16_lossy(&units));
+            }
+        }
+    }
+
+    fn binary_chunks(&mut self) -> Result<Vec<u8>, Error> {
+        let mut data = vec![];
+        loop {
+            let tag = self.byte()?;
+            let (len, last) = match tag {
+                0x20..=0x2f => ((tag - 0x20) as usize, true),
+                0x34..=0x37 => ((((tag - 0x34) as usize) << 8) | self.byte()? as usize, true),
+                b'B' => (self.u16()?, true),
+                b'A' => (self.u16()?, false),
+                _ => return Err(err!("hessian", format!("invalid binary tag {:#x}", tag))),
+            };
+            data.extend_from_slice(self.bytes(len)?);
+            if last {
+                return Ok(data);
+            }
+        }
+    }
+
+    /// a type is a string, or an int referring to an earlier one
+    fn type_name(&mut self) -> Result<String, Error> {
+        match self.peek()? {
+            0x00..=0x1f | 0x30..=0x33 | b'S' | b'R' => {
+                let t = self.string_chunks()?;
+                self.types.push(t.clone());
+                Ok(t)
+            }
+            _ => {
+                let i = self.int()? as usize;
+                self.types
+                    .get(i)
+                    .cloned()
+                    .ok_or(err!("hessian", "invalid type ref"))
+            }
+        }
+    }
+
+    fn list(&mut self, len: Option<usize>) -> Result<Value, Error> {
+        let idx = self.refs.len();
+        self.refs.push(Value::Null);
+        let mut list = vec![];
+        match len {
+            Some(len) => {
+                for _ in 0..len {
+                    list.push(self.value()?);
+                }
+            }
+            None => {
+                while self.peek()? != b'Z' {
+                    list.push(self.value()?);
+                }
+                self.pos += 1;
+            }
+        }
+        self.refs[idx] = Value::Array(list);
+        Ok(self.refs[idx].clone())
+    }
+
+    fn map(&mut self) -> Result<Value, Error> {
+        let idx = self.refs.len();
+        self.refs.push(Value::Null);
+        let mut map = Map::new();
+        while self.peek()? != b'Z' {
+            let k = match self.value()? {
+                Value::String(s) => s,
+                k => k.to_string(),
+            };
+            let v = self.value()?;
+            map.insert(k, v);
+        }
+        self.pos += 1;
+        self.refs[idx] = Value::Object(map);
+        Ok(self.refs[idx].clone())
+    }
+
+    fn object(&mut self, def: usize) -> Result<Value, Error> {
+        let fields = self
+            .classes
+            .get(def)
+            .cloned()
+            .ok_or(err!("hessian", "invalid class ref"))?;
+        let idx = self.refs.len();
+        self.refs.push(Value::Null);
+        let mut map = Map::new();
+        for f in fields.iter().skip(1) {
+            let v = self.value()?;
+            map.insert(f.clone(), v);
+        }
+        self.refs[idx] = Value::Object(map);
+        Ok(self.refs[idx].clone())
+    }
+
+    /// nesting is limited to `DEPTH_MAX`, so a hostile payload cannot overflow the stack
+    pub fn value(&mut self) -> Result<Value, Error> {
+        if self.depth >= DEPTH_MAX {
+            return Err(err!("hessian", "nested too deep"));
+        }
+        self.depth += 1;
+        let v = self.value0();
+        self.depth -= 1;
+        v
+    }
+
+    fn value0(&mut self) -> Result<Value, Error> {
+        let tag = self.byte()?;
+        let v = match tag {
+            b'N' => Value::Null,
+            b'T' => Value::Bool(true),
+            b'F' => Value::Bool(false),
+
+            0x80..=0xbf => Value::from(tag as i32 - 0x90),
+            0xc0..=0xcf => Value::from(((tag as i32 - 0xc8) << 8) | self.byte()? as i32),
+            0xd0..=0xd7 => {
+                let b = self.bytes(2)?;
+                Value::from(((tag as i32 - 0xd4) << 16) | ((b[0] as i32) << 8) | b[1] as i32)
+            }
+            b'I' => Value::from(self.i32()?),
+
+            0xd8..=0xef => Value::from(tag as i64 - 0xe0),
+            0xf0..=0xff => Value::from(((tag as i64 - 0xf8) << 8) | self.byte()? as i64),
+            0x38..=0x3f => {
+                let b = self.bytes(2)?;
+                Value::from(((tag as i64 - 0x3c) << 16) | ((b[0] as i64) << 8) | b[1] as i64)
+            }
+            0x59 => Value::from(self.i32()? as i64),
+            b'L' => Value::from(self.i64()?),
+
+            0x5b => double(0.0),
+            0x5c => double(1.0),
+            0x5d => double(self.byte()? as i8 as f64),
+            0x5e => {
+                let b = self.bytes(2)?;
+                double(i16::from_be_bytes([b[0], b[1]]) as f64)
+            }
+            0x5f => double(self.i32()? as f64 * 0.001),
+            b'D' => double(f64::from_bits(self.i64()? as u64)),
+
+            // dates are millis since epoch
+            0x4a => Value::from(self.i64()?),
+            0x4b => Value::from(self.i32()? as i64 * 60000),
+
+            0x00..=0x1f | 0x30..=0x33 | b'S' | b'R' => {
+                self.pos -= 1;
+                Value::String(self.string_chunks()?)
+            }
+            0x20..=0x2f | 0x34..=0x37 | b'B' | b'A' => {
+                self.pos -= 1;
+                Value::String(base64::encode(self.binary_chunks()?))
+            }
+
+            0x55 => {
+                self.type_name()?;
+                self.list(None)?
+            }
+            b'V' => {
+                self.type_name()?;
+                let len = self.int()? as usize;
+                self.list(Some(len))?
+            }
+            0x57 => self.list(None)?,
+            0x58 => {
+                let len = self.int()? as usize;
+                self.list(Some(len))?
+            }
+            0x70..=0x77 => {
+                self.type_name()?;
+                self.list(Some((tag - 0x70) as usize))?
+            }
+            0x78..=0x7f => self.list(Some((tag - 0x78) as usize))?,
+
+            b'M' => {
+                self.type_name()?;
+                self.map()?
+            }
+            b'H' => self.map()?,
+
+            b'C' => {
+                let name = self.string()?;
+                let len = self.int()? as usize;
+                let mut def = vec![name];
+                for _ in 0..len {
+                    def.push(self.string()?);
+                }
+                self.classes.push(def);
+                return self.value();
+            }
+            b'O' => {
+                let def = self.int()? as usize;
+                self.object(def)?
+            }
+            0x60..=0x6f => self.object((tag - 0x60) as usize)?,
+
+            0x51 => {
+                let i = self.int()? as usize;
+                self.refs
+                    .get(i)
+                    .cloned()
+                    .ok_or(err!("hessian", "invalid ref"))?
+            }
+            _ => return Err(err!("hessian", format!("unsupported tag {:#x}", tag))),
+        };
+        Ok(v)
+    }
+}
+
+fn double(d: f64) -> Value {
+    Number::from_f64(d)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+#[test]
+fn codec_test() {
+    let value = json!({
+        "name": "chord 测试 🦀",
+        "ints": [0, -16, 47, 2047, -262144, 262143, 2147483647, 9007199254740993i64],
+        "double": 1.5,
+        "long": "x".repeat(2000),
+        "nested": [{"a": null, "b": true}]
+    });
+    let mut e = Encoder::new();
+    e.value(&value);
+    e.list_begin(2, "[string");
+    e.string("a");
+    e.string("b");
+    let bytes = e.into_bytes();
+
+    let mut d = Decoder::new(bytes.as_slice());
+    assert_eq!(value, d.value().unwrap());
+    assert_eq!(json!(["a", "b"]), d.value().unwrap());
+}
+
+#[test]
+fn depth_test() {
+    let mut bytes = vec![0x79u8; DEPTH_MAX - 1];
+    bytes.push(b'N');
+    assert_eq!(
+        bytes.len() - 1,
+        Decoder::new(bytes.as_slice())
+            .value()
+            .unwrap()
+            .to_string()
+            .matches('[')
+            .count()
+    );
+
+    let bytes = vec![0x79u8; 100000];
+    assert!(Decoder::new(bytes.as_slice()).value().is_err());
+}
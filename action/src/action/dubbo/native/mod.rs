@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_std::future::timeout;
+use async_std::net::TcpStream;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use log::trace;
+
+use chord::action::prelude::*;
+use hessian::{Decoder, Encoder};
+use registry::Registry;
+
+mod hessian;
+mod registry;
+
+const MAGIC: [u8; 2] = [0xda, 0xbb];
+/// request, two way, hessian2
+const FLAG_REQUEST: u8 = 0x80 | 0x40 | 2;
+const FLAG_EVENT: u8 = 0x20;
+const STATUS_OK: u8 = 20;
+const DUBBO_VERSION: &str = "2.0.2";
+const GENERIC_DESC: &str = "Ljava/lang/String;[Ljava/lang/String;[Ljava/lang/Object;";
+/// the default payload limit of dubbo
+const PAYLOAD_MAX: u64 = 8 * 1024 * 1024;
+
+/// ```yaml
+/// dubbo:
+///     mode: native
+///     native:
+///         address: 127.0.0.1:20880
+///         payload_max: 8388608
+///         registry:
+///             protocol: zookeeper
+///             address: zookeeper://127.0.0.1:2181
+/// ```
+///
+/// providers are found at `address`, or else looked up in the `registry`,
+/// responses larger than `payload_max` bytes are rejected
+pub struct DubboFactory {
+    native: Arc<Native>,
+}
+
+struct Native {
+    address: Option<String>,
+    registry: Option<Registry>,
+    payload_max: usize,
+    providers: Mutex<HashMap<String, Vec<String>>>,
+    idle: Mutex<HashMap<String, Vec<TcpStream>>>,
+    request_id: AtomicU64,
+    round: AtomicUsize,
+}
+
+impl DubboFactory {
+    pub async fn new(config: Option<Value>) -> Result<DubboFactory, Error> {
+        let config = config.unwrap_or(Value::Null);
+        let native = &config["native"];
+        let registry = if native["registry"].is_null() {
+            None
+        } else {
+            Some(Registry::new(&native["registry"])?)
+        };
+
+        Ok(DubboFactory {
+            native: Arc::new(Native {
+                address: native["address"].as_str().map(|a| a.to_owned()),
+                registry,
+                payload_max: native["payload_max"].as_u64().unwrap_or(PAYLOAD_MAX) as usize,
+                providers: Mutex::new(HashMap::new()),
+                idle: Mutex::new(HashMap::new()),
+                request_id: AtomicU64::new(0),
+                round: AtomicUsize::new(0),
+            }),
+        })
+    }
+}
+
+#[async_trait]
+impl Factory for DubboFactory {
+    async fn create(&self, _: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        Ok(Box::new(Dubbo {
+            native: self.native.clone(),
+        }))
+    }
+}
+
+struct Dubbo {
+    native: Arc<Native>,
+}
+
+/// ```yaml
+/// method: com.bitranger.dubbo.provider.service.EchoService#echo(java.lang.String)
+/// args: [ "{{case.content}}" ]
+/// version: 1.0.0
+/// group: default
+/// address: 127.0.0.1:20880
+/// ```
+///
+/// invoked generically, so pojo arguments are given as maps and pojo results come back as maps,
+/// `address` skips the registry
+#[async_trait]
+impl Action for Dubbo {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let args = arg.args();
+        let method_long = args["method"]
+            .as_str()
+            .ok_or(err!("109", "missing method"))?;
+        let parts = method_long
+            .split(&['#', '(', ',', ')'][..])
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<&str>>();
+        if parts.len() < 2 {
+            return Err(err!("110", "invalid method"));
+        }
+        let method_args = args["args"]
+            .as_array()
+            .ok_or(err!("111", "args must be array"))?;
+
+        let interface = parts[0];
+        let version = args["version"].as_str();
+        let group = args["group"].as_str();
+
+        let provider = match args["address"].as_str() {
+            Some(a) => a.to_owned(),
+            None => self.native.provider(interface, version, group).await?,
+        };
+
+        let mut e = Encoder::new();
+        e.string(DUBBO_VERSION);
+        e.string(interface);
+        e.string(version.unwrap_or("0.0.0"));
+        e.string("$invoke");
+        e.string(GENERIC_DESC);
+        e.string(parts[1]);
+        e.list_begin(parts.len() - 2, "[string");
+        for t in &parts[2..] {
+            e.string(t);
+        }
+        e.list_begin(method_args.len(), "[object");
+        for a in method_args {
+            e.value(a);
+        }
+        e.map_begin();
+        let mut attachments = vec![
+            ("path", interface.to_owned()),
+            ("interface", interface.to_owned()),
+            ("generic", "true".to_owned()),
+            ("timeout", arg.timeout().as_millis().to_string()),
+        ];
+        if let Some(v) = version {
+            attachments.push(("version", v.to_owned()));
+        }
+        if let Some(g) = group {
+            attachments.push(("group", g.to_owned()));
+        }
+        for (k, v) in attachments {
+            e.string(k);
+            e.string(v.as_str());
+        }
+        e.end();
+
+        trace!("invoke {} {}", provider, method_long);
+        let (status, body) = timeout(
+            arg.timeout(),
+            self.native.invoke(provider.as_str(), e.into_bytes()),
+        )
+        .await
+        .or(Err(err!("116", "timeout")))??;
+        if status != STATUS_OK {
+            let message = Decoder::new(body.as_slice()).string().unwrap_or_default();
+            return Err(err!("114", format!("{}: {}", status_name(status), message)));
+        }
+
+        let mut d = Decoder::new(body.as_slice());
+        let value = match d.int()? {
+            1 | 4 => d.value()?,
+            2 | 5 => Value::Null,
+            0 | 3 => {
+                let ex = d.value()?;
+                let message = ex["exceptionMessage"]
+                    .as_str()
+                    .or_else(|| ex["detailMessage"].as_str())
+                    .map(|m| m.to_owned())
+                    .unwrap_or_else(|| ex.to_string());
+                return Err(err!("113", message));
+            }
+            f => return Err(err!("117", format!("invalid response flag {}", f))),
+        };
+        Ok(Box::new(value))
+    }
+}
+
+impl Native {
+    async fn provider(
+        &self,
+        interface: &str,
+        version: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<String, Error> {
+        if let Some(address) = self.address.as_ref() {
+            return Ok(address.clone());
+        }
+        let registry = self
+            .registry
+            .as_ref()
+            .ok_or(err!("106", "missing dubbo.native.address"))?;
+
+        let key = format!(
+            "{}:{}:{}",
+            interface,
+            version.unwrap_or(""),
+            group.unwrap_or("")
+        );
+        let cached = self.providers.lock().unwrap().get(&key).cloned();
+        let providers = match cached {
+            Some(p) => p,
+            None => {
+                let p = registry.lookup(interface, version, group).await?;
+                if !p.is_empty() {
+                    self.providers
+                        .lock()
+                        .unwrap()
+                        .insert(key.clone(), p.clone());
+                }
+                p
+            }
+        };
+        if providers.is_empty() {
+            return Err(err!("112", format!("no provider for {}", key)));
+        }
+        let i = self.round.fetch_add(1, Ordering::Relaxed);
+        Ok(providers[i % providers.len()].clone())
+    }
+
+    /// status and body of the response,
+    /// an idle connection is reused when there is one, and the request is resent on a fresh one
+    /// only when writing to the idle one failed, as it may have reached the provider otherwise
+    async fn invoke(&self, provider: &str, body: Vec<u8>) -> Result<(u8, Vec<u8>), Error> {
+        let idle = self
+            .idle
+            .lock()
+            .unwrap()
+            .get_mut(provider)
+            .and_then(|v| v.pop());
+        if let Some(mut stream) = idle {
+            if let Ok(id) = self.send(&mut stream, body.as_slice()).await {
+                let res = self
+                    .receive(&mut stream, id)
+                    .await
+                    .map_err(|e| err!("115", format!("{}: {}", provider, e)))?;
+                self.release(provider, stream);
+                return Ok(res);
+            }
+        }
+
+        let mut stream = match TcpStream::connect(provider).await {
+            Ok(s) => s,
+            Err(e) => {
+                // the registry may be stale
+                self.providers.lock().unwrap().clear();
+                return Err(err!("115", format!("{}: {}", provider, e)));
+            }
+        };
+        let id = self
+            .send(&mut stream, body.as_slice())
+            .await
+            .map_err(|e| err!("115", format!("{}: {}", provider, e)))?;
+        let res = self
+            .receive(&mut stream, id)
+            .await
+            .map_err(|e| err!("115", format!("{}: {}", provider, e)))?;
+        self.release(provider, stream);
+        Ok(res)
+    }
+
+    fn release(&self, provider: &str, stream: TcpStream) {
+        self.idle
+            .lock()
+            .unwrap()
+            .entry(provider.to_owned())
+            .or_default()
+            .push(stream);
+    }
+
+    /// id of the request written
+    async fn send<S: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        body: &[u8],
+    ) -> Result<u64, std::io::Error> {
+        let id = self.request_id.fetch_add(1, Ordering::Relaxed);
+        let mut header = [0u8; 16];
+        header[0..2].copy_from_slice(&MAGIC);
+        header[2] = FLAG_REQUEST;
+        header[4..12].copy_from_slice(&id.to_be_bytes());
+        header[12..16].copy_from_slice(&(body.len() as u32).to_be_bytes());
+        stream.write_all(&header).await?;
+        stream.write_all(body).await?;
+        Ok(id)
+    }
+
+    async fn receive<S: AsyncRead + Unpin>(
+        &self,
+        stream: &mut S,
+        id: u64,
+    ) -> Result<(u8, Vec<u8>), std::io::Error> {
+        loop {
+            let mut header = [0u8; 16];
+            stream.read_exact(&mut header).await?;
+            if header[0..2] != MAGIC {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "invalid magic",
+                ));
+            }
+            let mut len = [0u8; 4];
+            len.copy_from_slice(&header[12..16]);
+            let len = u32::from_be_bytes(len) as usize;
+            if len > self.payload_max {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("payload {} exceeds {}", len, self.payload_max),
+                ));
+            }
+            let mut body = vec![0u8; len];
+            stream.read_exact(&mut body).await?;
+
+            let mut res_id = [0u8; 8];
+            res_id.copy_from_slice(&header[4..12]);
+            // heartbeats and other requests from the provider are skipped
+            if header[2] & (0x80 | FLAG_EVENT) != 0 || u64::from_be_bytes(res_id) != id {
+                continue;
+            }
+            return Ok((header[3], body));
+        }
+    }
+}
+
+fn status_name(status: u8) -> &'static str {
+    match status {
+        30 => "client timeout",
+        31 => "server timeout",
+        35 => "channel inactive",
+        40 => "bad request",
+        50 => "bad response",
+        60 => "service not found",
+        70 => "service error",
+        80 => "server error",
+        90 => "client error",
+        100 => "server threadpool exhausted",
+        _ => "unknown status",
+    }
+}
+
+#[test]
+fn receive_test() {
+    let native = Native {
+        address: None,
+        registry: None,
+        payload_max: 4,
+        providers: Mutex::new(HashMap::new()),
+        idle: Mutex::new(HashMap::new()),
+        request_id: AtomicU64::new(7),
+        round: AtomicUsize::new(0),
+    };
+    let frame = |flag: u8, id: u64, body: &[u8]| {
+        let mut f = vec![0u8; 16];
+        f[0..2].copy_from_slice(&MAGIC);
+        f[2] = flag;
+        f[3] = STATUS_OK;
+        f[4..12].copy_from_slice(&id.to_be_bytes());
+        f[12..16].copy_from_slice(&(body.len() as u32).to_be_bytes());
+        f.extend_from_slice(body);
+        f
+    };
+
+    async_std::task::block_on(async {
+        let mut req = vec![];
+        let id = native.send(&mut req, b"ab").await.unwrap();
+        assert_eq!(7, id);
+        assert_eq!(18, req.len());
+
+        let mut res = frame(2 | FLAG_EVENT, 0, b"");
+        res.extend(frame(2, 6, b"x"));
+        res.extend(frame(2, 7, b"abcd"));
+        let res = native.receive(&mut res.as_slice(), id).await.unwrap();
+        assert_eq!((STATUS_OK, b"abcd".to_vec()), res);
+
+        let res = frame(2, 7, b"abcde");
+        assert!(native.receive(&mut res.as_slice(), id).await.is_err());
+    });
+}
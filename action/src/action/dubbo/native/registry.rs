@@ -0,0 +1,237 @@
+use std::str::FromStr;
+
+use async_std::net::TcpStream;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use surf::Url;
+
+use chord::action::prelude::*;
+
+/// ```yaml
+/// registry:
+///     protocol: zookeeper
+///     address: zookeeper://127.0.0.1:2181
+///     namespace: public
+/// ```
+///
+/// `protocol` is zookeeper or nacos, `namespace` only applies to nacos
+pub struct Registry {
+    protocol: String,
+    address: String,
+    namespace: Option<String>,
+}
+
+impl Registry {
+    pub fn new(config: &Value) -> Result<Registry, Error> {
+        let protocol = config["protocol"]
+            .as_str()
+            .unwrap_or("zookeeper")
+            .to_owned();
+        let address = config["address"]
+            .as_str()
+            .ok_or(err!("103", "missing dubbo.native.registry.address"))?;
+        let address = match address.find("://") {
+            Some(i) => &address[i + 3..],
+            None => address,
+        };
+        Ok(Registry {
+            protocol,
+            address: address.trim_end_matches('/').to_owned(),
+            namespace: config["namespace"].as_str().map(|n| n.to_owned()),
+        })
+    }
+
+    /// `host:port` of every dubbo provider of the service
+    pub async fn lookup(
+        &self,
+        interface: &str,
+        version: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        match self.protocol.as_str() {
+            "zookeeper" => zookeeper(self.address.as_str(), interface, version, group).await,
+            "nacos" => nacos(self, interface, version, group).await,
+            p => Err(err!("104", format!("unsupported registry {}", p))),
+        }
+    }
+}
+
+/// providers are url encoded children of `/dubbo/<interface>/providers`
+async fn zookeeper(
+    address: &str,
+    interface: &str,
+    version: Option<&str>,
+    group: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    let path = format!("/dubbo/{}/providers", interface);
+    let children = zk_children(address, path.as_str())
+        .await
+        .map_err(|e| err!("105", format!("zookeeper {}: {}", address, e)))?;
+
+    let mut providers = vec![];
+    for child in children {
+        let decoded = urlencoding::decode(child.as_str()).unwrap_or(child);
+        let url = match Url::parse(decoded.as_str()) {
+            Ok(u) if u.scheme() == "dubbo" => u,
+            _ => continue,
+        };
+        let param = |key: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.to_string())
+        };
+        if !param_matches(param("version"), version) || !param_matches(param("group"), group) {
+            continue;
+        }
+        if let (Some(host), Some(port)) = (url.host_str(), url.port()) {
+            providers.push(format!("{}:{}", host, port));
+        }
+    }
+    Ok(providers)
+}
+
+fn param_matches(provided: Option<String>, wanted: Option<&str>) -> bool {
+    let provided = provided.unwrap_or_default();
+    let wanted = wanted.unwrap_or("");
+    let none = |v: &str| v.is_empty() || v == "0.0.0";
+    provided == wanted || (none(provided.as_str()) && none(wanted))
+}
+
+/// a session just long enough for one `getChildren`
+async fn zk_children(address: &str, path: &str) -> Result<Vec<String>, std::io::Error> {
+    let mut stream = TcpStream::connect(address).await?;
+
+    let mut connect = vec![];
+    connect.extend_from_slice(&0i32.to_be_bytes()); // protocol version
+    connect.extend_from_slice(&0i64.to_be_bytes()); // last zxid seen
+    connect.extend_from_slice(&10000i32.to_be_bytes()); // session timeout
+    connect.extend_from_slice(&0i64.to_be_bytes()); // session id
+    connect.extend_from_slice(&16i32.to_be_bytes());
+    connect.extend_from_slice(&[0u8; 16]); // password
+    connect.push(0); // read only
+    zk_write(&mut stream, connect).await?;
+    let res = zk_read(&mut stream).await?;
+    if res.len() < 8 || i32::from_be_bytes([res[4], res[5], res[6], res[7]]) <= 0 {
+        return Err(zk_error("session rejected"));
+    }
+
+    let mut request = vec![];
+    request.extend_from_slice(&1i32.to_be_bytes()); // xid
+    request.extend_from_slice(&8i32.to_be_bytes()); // getChildren
+    request.extend_from_slice(&(path.len() as i32).to_be_bytes());
+    request.extend_from_slice(path.as_bytes());
+    request.push(0); // watch
+    zk_write(&mut stream, request).await?;
+
+    let res = loop {
+        let res = zk_read(&mut stream).await?;
+        // skip pings and notifications
+        if res.len() >= 4 && i32::from_be_bytes([res[0], res[1], res[2], res[3]]) == 1 {
+            break res;
+        }
+    };
+
+    let mut close = vec![];
+    close.extend_from_slice(&2i32.to_be_bytes());
+    close.extend_from_slice(&(-11i32).to_be_bytes());
+    let _ = zk_write(&mut stream, close).await;
+
+    // reply header is xid, zxid, err
+    let mut r = ZkReader { buf: &res, pos: 12 };
+    match r.i32().ok_or(zk_error("invalid response"))? {
+        0 => {}
+        // no node, nobody has registered yet
+        -101 => return Ok(vec![]),
+        e => return Err(zk_error(format!("error code {}", e).as_str())),
+    }
+    let count = r.i32().ok_or(zk_error("invalid response"))?;
+    let mut children = vec![];
+    for _ in 0..count.max(0) {
+        children.push(r.string().ok_or(zk_error("invalid response"))?);
+    }
+    Ok(children)
+}
+
+async fn zk_write(stream: &mut TcpStream, body: Vec<u8>) -> Result<(), std::io::Error> {
+    stream.write_all(&(body.len() as i32).to_be_bytes()).await?;
+    stream.write_all(body.as_slice()).await
+}
+
+async fn zk_read(stream: &mut TcpStream) -> Result<Vec<u8>, std::io::Error> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+    let mut body = vec![0u8; i32::from_be_bytes(len).max(0) as usize];
+    stream.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+fn zk_error(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+struct ZkReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ZkReader<'a> {
+    fn i32(&mut self) -> Option<i32> {
+        let b = self.buf.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.i32()?.max(0) as usize;
+        let b = self.buf.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(String::from_utf8_lossy(b).to_string())
+    }
+}
+
+/// providers are registered as `providers:<interface>:<version>:<group>`
+async fn nacos(
+    registry: &Registry,
+    interface: &str,
+    version: Option<&str>,
+    group: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    let service = format!(
+        "providers:{}:{}:{}",
+        interface,
+        version.unwrap_or(""),
+        group.unwrap_or("")
+    );
+    let mut url =
+        Url::from_str(format!("http://{}/nacos/v1/ns/instance/list", registry.address).as_str())
+            .or(Err(err!("103", "invalid dubbo.native.registry.address")))?;
+    url.query_pairs_mut()
+        .append_pair("serviceName", service.as_str())
+        .append_pair("healthyOnly", "true");
+    if let Some(ns) = registry.namespace.as_ref() {
+        url.query_pairs_mut()
+            .append_pair("namespaceId", ns.as_str());
+    }
+
+    let mut res = surf::get(url)
+        .await
+        .map_err(|e| err!("105", format!("nacos {}", e)))?;
+    if !res.status().is_success() {
+        return Err(err!("105", format!("nacos {}", res.status())));
+    }
+    let body: Value = res
+        .body_json()
+        .await
+        .map_err(|e| err!("105", format!("nacos {}", e)))?;
+
+    let providers = body["hosts"]
+        .as_array()
+        .map(|hosts| {
+            hosts
+                .iter()
+                .filter(|h| h["enabled"].as_bool().unwrap_or(true))
+                .filter_map(|h| Some(format!("{}:{}", h["ip"].as_str()?, h["port"].as_u64()?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(providers)
+}
@@ -1,27 +1,43 @@
-use async_std::net::{Shutdown, TcpStream};
-use chord::action::prelude::*;
-use chord::value::to_string;
-use futures::io::{AsyncReadExt, AsyncWriteExt};
+use std::sync::{Arc, Mutex};
+
+use async_std::future::timeout;
+use async_std::net::TcpStream;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use log::trace;
-use std::str::FromStr;
 
+use chord::action::prelude::*;
+use chord::value::{from_str, to_string};
+
+const PROMPT: &[u8] = b"dubbo>";
+
+/// ```yaml
+/// dubbo:
+///     mode: telnet
+///     telnet:
+///         address: 127.0.0.1:20880
+/// ```
+///
+/// `address` is the dubbo port or the qos port of a provider
 pub struct DubboFactory {
+    telnet: Arc<Telnet>,
+}
+
+struct Telnet {
     address: String,
+    idle: Mutex<Vec<TcpStream>>,
 }
 
 impl DubboFactory {
     pub async fn new(config: Option<Value>) -> Result<DubboFactory, Error> {
-        if config.is_none() {
-            return Err(err!("dubbo", "missing dubbo.config"));
-        }
-
-        let config = config.as_ref().unwrap();
-
+        let config = config.unwrap_or(Value::Null);
         let address = config["telnet"]["address"]
             .as_str()
-            .ok_or(err!("010", "missing telnet.address"))?;
+            .ok_or(err!("103", "missing dubbo.telnet.address"))?;
         Ok(DubboFactory {
-            address: address.to_owned(),
+            telnet: Arc::new(Telnet {
+                address: address.to_owned(),
+                idle: Mutex::new(vec![]),
+            }),
         })
     }
 }
@@ -30,121 +46,135 @@ impl DubboFactory {
 impl Factory for DubboFactory {
     async fn create(&self, _: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
         Ok(Box::new(Dubbo {
-            address: self.address.clone(),
+            telnet: self.telnet.clone(),
         }))
     }
 }
 
 struct Dubbo {
-    address: String,
+    telnet: Arc<Telnet>,
 }
 
+/// ```yaml
+/// method: com.bitranger.dubbo.provider.service.EchoService#echo(java.lang.String)
+/// args: [ "{{case.content}}" ]
+/// ```
+///
+/// the value is `{data, elapsed}`, `data` is the result printed by `invoke`, `elapsed` is in ms
 #[async_trait]
 impl Action for Dubbo {
     async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
-        let mut stream = TcpStream::connect(self.address.as_str())
-            .await
-            .map_err(|e| err!("connection error", format!("{}", e)))?;
-
         let method_long = arg.args()["method"]
             .as_str()
-            .ok_or(err!("010", "missing method"))?;
+            .ok_or(err!("109", "missing method"))?;
         let parts = method_long
             .split(&['#', '(', ',', ')'][..])
             .map(|s| s.trim())
             .filter(|s| !s.is_empty())
             .collect::<Vec<&str>>();
         if parts.len() < 2 {
-            return Err(err!("010", "invalid method"));
+            return Err(err!("110", "invalid method"));
         }
 
-        let args_raw = &arg.args()["args"];
-        let args: Vec<Value> = match args_raw {
-            Value::Array(aw_vec) => {
-                let mut ar_vec: Vec<Value> = vec![];
-                for aw in aw_vec {
-                    let ar = arg.render_value(aw)?;
-                    ar_vec.push(ar);
-                }
-                ar_vec
-            }
-            _ => arg
-                .render_value(args_raw)?
-                .as_array()
-                .ok_or(err!("010", "missing args"))?
-                .clone(),
-        };
-        let args_invoke: Vec<String> = args
-            .into_iter()
-            .map(|a| to_string(&a))
-            .filter(|a| a.is_ok())
-            .map(|a| a.unwrap())
-            .collect();
-        let args_invoke = args_invoke.join(",");
-
-        let invoke = format!("invoke {}.{}({})", parts[0], parts[1], args_invoke);
-        trace!("{}", invoke);
-        stream.write_all(invoke.as_bytes()).await?;
-        stream.shutdown(Shutdown::Write)?;
-
-        let suffix = "dubbo>".as_bytes();
-        let mut response = vec![0; 0];
-        let mut seek_idx = 0;
-        let mut resp = String::new();
-        let size = stream.read_to_string(&mut resp);
-        // loop {
-        //     let rx = stream.read(&mut buf).await?;
-        //     if
-        //     response.extend(&buf);
-        //     println!("{}", unsafe {
-        //         String::from_utf8_unchecked(response.clone())
-        //     });
-        //     match sub_vec_index(&response[seek_idx..], &suffix) {
-        //         Some(i) => {
-        //             response.truncate(seek_idx + i);
-        //             break;
-        //         }
-        //         None => {
-        //             seek_idx = std::cmp::min(response.len() - 1, response.len() - suffix.len());
-        //         }
-        //     }
-        //     if rx.is_err() {
-        //         break;
-        //     }
-        // }
-
-        // let mut value = unsafe { String::from_utf8_unchecked(Vec::from(response)) };
-        trace!("response: {}", resp);
-        // let i = resp.rfind("\r\nelapsed:");
-        // match i {
-        //     Some(i) => {
-        //         value.truncate(i);
-        //         let json = Value::from_str(value.as_str())?;
-        //         Ok(Box::new(json))
-        //     }
-        //     None => Err(err!("001", value)),
-        // }
-
-        let json = Value::from_str(resp.as_str())?;
-        Ok(Box::new(json))
+        let args = arg.args()["args"]
+            .as_array()
+            .ok_or(err!("111", "args must be array"))?;
+        let args_invoke = args
+            .iter()
+            .map(to_string)
+            .collect::<Result<Vec<String>, _>>()?
+            .join(",");
+
+        let invoke = format!("invoke {}.{}({})\r\n", parts[0], parts[1], args_invoke);
+        trace!("{}", invoke.trim_end());
+        let response = timeout(arg.timeout(), self.telnet.invoke(invoke.as_bytes()))
+            .await
+            .or(Err(err!("116", "timeout")))??;
+        trace!("response: {}", response);
+
+        Ok(Box::new(response_parse(response.as_str())?))
     }
 }
 
-fn sub_vec_index(vec: &[u8], sub_vec: &[u8]) -> Option<usize> {
-    let mut sub_vec_index = 0;
-    let mut match_size = 0;
-    for (i, u) in vec.iter().enumerate() {
-        if sub_vec[i - sub_vec_index].eq(u) {
-            match_size += 1;
-            if match_size == sub_vec.len() {
-                return Some(sub_vec_index);
+impl Telnet {
+    /// an idle connection is reused when there is one, and the command is resent on a fresh one
+    /// only when writing to the idle one failed, as it may have been invoked otherwise
+    async fn invoke(&self, command: &[u8]) -> Result<String, Error> {
+        let idle = self.idle.lock().unwrap().pop();
+        if let Some(mut stream) = idle {
+            if stream.write_all(command).await.is_ok() {
+                let res = receive(&mut stream)
+                    .await
+                    .map_err(|e| err!("115", format!("{}: {}", self.address, e)))?;
+                self.idle.lock().unwrap().push(stream);
+                return Ok(res);
             }
-        } else {
-            sub_vec_index = i + 1;
+        }
+
+        let mut stream = TcpStream::connect(self.address.as_str())
+            .await
+            .map_err(|e| err!("112", format!("{}: {}", self.address, e)))?;
+        stream
+            .write_all(command)
+            .await
+            .map_err(|e| err!("115", format!("{}: {}", self.address, e)))?;
+        let res = receive(&mut stream)
+            .await
+            .map_err(|e| err!("115", format!("{}: {}", self.address, e)))?;
+        self.idle.lock().unwrap().push(stream);
+        Ok(res)
+    }
+}
+
+/// everything printed before the next prompt
+async fn receive<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String, std::io::Error> {
+    let mut response = vec![];
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ));
+        }
+        let seek = response.len().saturating_sub(PROMPT.len());
+        response.extend_from_slice(&buf[..n]);
+        if let Some(i) = sub_vec_index(&response[seek..], PROMPT) {
+            response.truncate(seek + i);
+            return Ok(String::from_utf8_lossy(&response).to_string());
         }
     }
+}
 
-    return None;
+/// `[result: ]<json>\r\nelapsed: <n> ms.`, anything else is the reason the invocation failed
+fn response_parse(response: &str) -> Result<Value, Error> {
+    let response = response.trim();
+    let i = response
+        .rfind("elapsed:")
+        .ok_or(err!("113", response.to_owned()))?;
+
+    let data = response[..i].trim();
+    let data = data.strip_prefix("result:").unwrap_or(data).trim();
+    let data: Value =
+        from_str(data).map_err(|_| err!("114", format!("invalid result: {}", data)))?;
+
+    let elapsed = response[i + "elapsed:".len()..]
+        .trim()
+        .trim_end_matches('.')
+        .trim_end_matches("ms")
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| err!("114", format!("invalid elapsed: {}", response)))?;
+
+    Ok(json!({
+        "data": data,
+        "elapsed": elapsed
+    }))
+}
+
+fn sub_vec_index(vec: &[u8], sub_vec: &[u8]) -> Option<usize> {
+    vec.windows(sub_vec.len()).position(|w| w == sub_vec)
 }
 
 #[test]
@@ -156,3 +186,29 @@ fn sub_vec_index_test() {
 
     assert_eq!(true, sub_vec_index(&vec, &vec![7, 8, 9]).is_none());
 }
+
+#[test]
+fn response_parse_test() {
+    let value = response_parse("result: {\"a\":1}\r\nelapsed: 3 ms.\r\n").unwrap();
+    assert_eq!(json!({"data": {"a": 1}, "elapsed": 3}), value);
+
+    let value = response_parse("\"hello\"\r\nelapsed: 0 ms.").unwrap();
+    assert_eq!(json!("hello"), value["data"]);
+
+    let err = response_parse("No such method echo in service EchoService").unwrap_err();
+    assert_eq!("113", err.code());
+}
+
+#[test]
+fn receive_test() {
+    async_std::task::block_on(async {
+        let mut res = &b"result: 1\r\nelapsed: 0 ms.\r\ndubbo>"[..];
+        assert_eq!(
+            "result: 1\r\nelapsed: 0 ms.\r\n",
+            receive(&mut res).await.unwrap()
+        );
+
+        let mut res = &b"result: 1\r\n"[..];
+        assert!(receive(&mut res).await.is_err());
+    });
+}
@@ -0,0 +1,259 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use async_process::{Command, Stdio};
+use async_std::future::timeout;
+use async_std::path::PathBuf;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use log::trace;
+
+use chord::action::prelude::*;
+use chord::value::from_str;
+
+use crate::action::workdir::check_path;
+
+/// bytes kept from each of stdout and stderr
+const OUTPUT_MAX: u64 = 1024 * 1024;
+
+pub struct ExecFactory {
+    workdir: PathBuf,
+}
+
+impl ExecFactory {
+    pub async fn new(config: Option<Value>) -> Result<ExecFactory, Error> {
+        let config = config
+            .filter(|c| !c.is_null())
+            .ok_or(err!("100", "missing config"))?;
+
+        let workdir = config["workdir"]
+            .as_str()
+            .ok_or(err!("101", "missing workdir"))?;
+        let workdir = PathBuf::from_str(workdir)?;
+        async_std::fs::create_dir_all(workdir.as_path()).await?;
+
+        Ok(ExecFactory { workdir })
+    }
+}
+
+#[async_trait]
+impl Factory for ExecFactory {
+    async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let dir = self.workdir.join(arg.id().to_string());
+        async_std::fs::create_dir_all(dir.as_path()).await?;
+        trace!("exec dir create {}", dir.as_path().to_str().unwrap_or(""));
+        Ok(Box::new(Exec {
+            workdir: self.workdir.clone(),
+            dir,
+        }))
+    }
+}
+
+struct Exec {
+    workdir: PathBuf,
+    dir: PathBuf,
+}
+
+/// ```yaml
+/// command: sh
+/// args:
+///     - "-c"
+///     - "jq '.amount * 100' && echo done >&2"
+/// env:
+///     LANG: C
+/// env_clear: false
+/// cwd: ["{{step.download.value.path.0}}"]
+/// stdin: { "amount": "{{case.amount}}" }
+/// json: false
+/// output_max: 1048576
+/// timeout: 10
+/// ```
+///
+/// runs `command` in the directory of this action under the workdir, or in `cwd`,
+/// a path `[<action dir>, <dir>...]` owned by this task like `fstore`.
+/// `stdin` strings go raw, other values as json text.
+/// the value is `{code, success, stdout, stderr, stdout_truncated, stderr_truncated, duration}`,
+/// `code` is null when the process was killed by a signal, `duration` in milliseconds.
+/// at most `output_max` bytes of each output are kept, `json: true` parses stdout.
+/// the child runs in a process group of its own, which is killed on timeout or when the step is dropped
+#[async_trait]
+impl Action for Exec {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let args = arg.args();
+        let program = args["command"]
+            .as_str()
+            .ok_or(err!("102", "missing command"))?;
+
+        let mut command = Command::new(program);
+        if let Some(list) = args["args"].as_array() {
+            command.args(list.iter().map(text));
+        }
+        if args["env_clear"].as_bool().unwrap_or(false) {
+            command.env_clear();
+        }
+        if let Some(env) = args["env"].as_object() {
+            command.envs(env.iter().map(|(k, v)| (k, text(v))));
+        }
+        let cwd = match &args["cwd"] {
+            Value::Null => self.dir.clone(),
+            path => cwd_create(self.workdir.clone(), arg, path)?,
+        };
+        command
+            .current_dir(cwd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        group_create(&mut command);
+
+        let start = Instant::now();
+        let mut child = command
+            .spawn()
+            .map_err(|e| err!("104", format!("spawn {} failure: {}", program, e)))?;
+        let mut group = Group(Some(child.id()));
+        trace!("exec spawn {} {}", program, child.id());
+
+        let input = match &args["stdin"] {
+            Value::Null => None,
+            v => Some(text(v).into_bytes()),
+        };
+        let stdin = child.stdin.take();
+        let write = async move {
+            if let (Some(mut stdin), Some(input)) = (stdin, input) {
+                // the child may exit without reading it
+                let _ = stdin.write_all(&input).await;
+                let _ = stdin.close().await;
+            }
+        };
+
+        let max = args["output_max"].as_u64().unwrap_or(OUTPUT_MAX);
+        let stdout = child.stdout.take().ok_or(err!("104", "missing stdout"))?;
+        let stderr = child.stderr.take().ok_or(err!("104", "missing stderr"))?;
+        let duration = args["timeout"]
+            .as_u64()
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| arg.timeout());
+
+        let ((), (stdout, stdout_truncated), (stderr, stderr_truncated)) = timeout(
+            duration,
+            futures::future::try_join3(
+                async {
+                    write.await;
+                    Ok::<(), std::io::Error>(())
+                },
+                capture(stdout, max),
+                capture(stderr, max),
+            ),
+        )
+        .await
+        .or(Err(err!("106", "timeout")))??;
+        let status = timeout(duration.saturating_sub(start.elapsed()), child.status())
+            .await
+            .or(Err(err!("106", "timeout")))??;
+        group.0 = None;
+        let elapsed = start.elapsed();
+
+        let stdout = String::from_utf8_lossy(&stdout).to_string();
+        let stdout = if args["json"].as_bool().unwrap_or(false) {
+            from_str(stdout.as_str())
+                .map_err(|e| err!("105", format!("illegal json stdout: {}", e)))?
+        } else {
+            Value::String(stdout)
+        };
+
+        Ok(Box::new(json!({
+            "code": status.code(),
+            "success": status.success(),
+            "stdout": stdout,
+            "stderr": String::from_utf8_lossy(&stderr),
+            "stdout_truncated": stdout_truncated,
+            "stderr_truncated": stderr_truncated,
+            "duration": elapsed.as_millis() as u64
+        })))
+    }
+}
+
+/// keeps the first `max` bytes and drains the rest, so the child never blocks on a full pipe
+async fn capture(
+    mut reader: impl AsyncRead + Unpin,
+    max: u64,
+) -> Result<(Vec<u8>, bool), std::io::Error> {
+    let mut kept = Vec::new();
+    let mut buf = [0u8; 8192];
+    let mut truncated = false;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok((kept, truncated));
+        }
+        let room = (max as usize).saturating_sub(kept.len());
+        kept.extend_from_slice(&buf[..n.min(room)]);
+        truncated |= n > room;
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn cwd_create(mut dir: PathBuf, arg: &dyn RunArg, path: &Value) -> Result<PathBuf, Error> {
+    let pav: Vec<&str> = path
+        .as_array()
+        .ok_or(err!("103", "cwd must be a path array"))?
+        .iter()
+        .filter_map(|p| p.as_str())
+        .collect();
+
+    if pav.is_empty() {
+        return Err(err!("103", "missing cwd"));
+    }
+    if !check_path(arg.id().case_id().task_id().to_string().as_str(), &pav) {
+        return Err(err!("103", "forbidden access"));
+    }
+    for pa in pav {
+        dir = dir.join(pa);
+    }
+    Ok(dir)
+}
+
+/// kills the process group of the child, the child and whatever it forked,
+/// unless the child is reaped and its pid may be reused
+struct Group(Option<u32>);
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(id) = self.0 {
+            unsafe {
+                libc::killpg(id as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn group_create(command: &mut Command) {
+    use async_process::unix::CommandExt;
+    unsafe {
+        command.pre_exec(|| {
+            libc::setpgid(0, 0);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn group_create(_: &mut Command) {}
+
+#[test]
+fn capture_test() {
+    let (kept, truncated) = async_std::task::block_on(capture(&b"hello world"[..], 5)).unwrap();
+    assert_eq!(b"hello".to_vec(), kept);
+    assert!(truncated);
+
+    let (kept, truncated) = async_std::task::block_on(capture(&b"hi"[..], 5)).unwrap();
+    assert_eq!(b"hi".to_vec(), kept);
+    assert!(!truncated);
+}
@@ -0,0 +1,245 @@
+use std::str::FromStr;
+
+use async_std::fs;
+use async_std::io::prelude::WriteExt;
+use async_std::path::PathBuf;
+use futures::StreamExt;
+use log::trace;
+use sha2::Digest;
+
+use chord::action::prelude::*;
+use chord::value::{from_slice, from_str, to_string_pretty, Map};
+
+use crate::action::workdir::check_path;
+
+pub struct FileFactory {
+    workdir: PathBuf,
+}
+
+impl FileFactory {
+    pub async fn new(config: Option<Value>) -> Result<FileFactory, Error> {
+        let config = config
+            .filter(|c| !c.is_null())
+            .ok_or(err!("100", "missing config"))?;
+
+        let workdir = config["workdir"]
+            .as_str()
+            .ok_or(err!("101", "missing workdir"))?;
+        let workdir = PathBuf::from_str(workdir)?;
+        fs::create_dir_all(workdir.as_path()).await?;
+
+        Ok(FileFactory { workdir })
+    }
+}
+
+#[async_trait]
+impl Factory for FileFactory {
+    async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let tmp = self.workdir.join(arg.id().to_string());
+        fs::create_dir_all(tmp.as_path()).await?;
+        trace!("tmp create {}", tmp.as_path().to_str().unwrap_or(""));
+        Ok(Box::new(File {
+            name: arg.id().to_string(),
+            workdir: self.workdir.clone(),
+            tmp,
+        }))
+    }
+}
+
+struct File {
+    name: String,
+    workdir: PathBuf,
+    tmp: PathBuf,
+}
+
+/// ```yaml
+/// operation: write
+/// name: "order-{{case.id}}.json"
+/// content: { "id": "{{case.id}}" }
+/// format: json
+/// ```
+///
+/// ```yaml
+/// operation: read
+/// path: ["{{step.step1.value.path.0}}", "{{step.step1.value.path.1}}"]
+/// format: csv
+/// ```
+///
+/// operations and their args
+///
+/// * `write`: `name` in the directory of this action or `path`, `content`, `format`, `append`
+/// * `read`: `path`, `format`
+/// * `list`: `path` of a directory
+/// * `checksum`: `path`, `algorithm` of `sha256` or `md5`, `sha256` by default
+///
+/// `format` is `text`, `json`, `yaml`, `csv` or `base64`, `text` by default.
+/// `csv` reads rows as objects keyed by the header, it is not written.
+/// `path` is `[<action dir>, <file>...]` owned by this task like `fstore`,
+/// `write` returns it as `{path, size}`
+#[async_trait]
+impl Action for File {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let args = arg.args();
+        let op = args["operation"]
+            .as_str()
+            .ok_or(err!("102", "missing operation"))?;
+        let format = args["format"].as_str().unwrap_or("text");
+
+        let value = match op {
+            "write" => {
+                let (file, path) = match args["name"].as_str() {
+                    Some(name) => {
+                        check_name(name)?;
+                        (self.tmp.join(name), json!([self.name, name]))
+                    }
+                    None => (
+                        path_create(self.workdir.clone(), arg, &args["path"])?,
+                        args["path"].clone(),
+                    ),
+                };
+                let content = content_create(&args["content"], format)?;
+                let mut options = fs::OpenOptions::new();
+                options.create(true).write(true);
+                if args["append"].as_bool().unwrap_or(false) {
+                    options.append(true);
+                } else {
+                    options.truncate(true);
+                }
+                let mut f = options.open(file.as_path()).await?;
+                f.write_all(&content).await?;
+                f.flush().await?;
+                let size = f.metadata().await?.len();
+                json!({ "path": path, "size": size })
+            }
+            "read" => {
+                let file = path_create(self.workdir.clone(), arg, &args["path"])?;
+                let content = fs::read(file.as_path()).await?;
+                read(content, format)?
+            }
+            "list" => {
+                let dir = path_create(self.workdir.clone(), arg, &args["path"])?;
+                let mut entries = fs::read_dir(dir.as_path()).await?;
+                let mut list = Vec::new();
+                while let Some(entry) = entries.next().await {
+                    let entry = entry?;
+                    let meta = entry.metadata().await?;
+                    list.push(json!({
+                        "name": entry.file_name().to_string_lossy(),
+                        "dir": meta.is_dir(),
+                        "size": meta.len()
+                    }));
+                }
+                list.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+                Value::Array(list)
+            }
+            "checksum" => {
+                let file = path_create(self.workdir.clone(), arg, &args["path"])?;
+                let content = fs::read(file.as_path()).await?;
+                let algorithm = args["algorithm"].as_str().unwrap_or("sha256");
+                let digest = match algorithm {
+                    "sha256" => format!("{:x}", sha2::Sha256::digest(&content)),
+                    "md5" => format!("{:x}", md5::compute(&content)),
+                    _ => return Err(err!("105", "illegal algorithm")),
+                };
+                json!({ "algorithm": algorithm, "value": digest, "size": content.len() })
+            }
+            _ => return Err(err!("106", "illegal operation")),
+        };
+        Ok(Box::new(value))
+    }
+}
+
+fn content_create(content: &Value, format: &str) -> Result<Vec<u8>, Error> {
+    let content = match (format, content) {
+        ("text", Value::String(s)) => s.clone().into_bytes(),
+        ("text", v) | ("json", v) => match v {
+            // a string holding json is written as is
+            Value::String(s) => {
+                from_str::<Value>(s.as_str())?;
+                s.clone().into_bytes()
+            }
+            v => to_string_pretty(v)?.into_bytes(),
+        },
+        ("yaml", v) => serde_yaml::to_string(v)
+            .map_err(|e| err!("107", e.to_string()))?
+            .into_bytes(),
+        ("base64", Value::String(s)) => {
+            base64::decode(s).map_err(|e| err!("107", format!("illegal base64: {}", e)))?
+        }
+        _ => return Err(err!("107", format!("illegal content for {}", format))),
+    };
+    Ok(content)
+}
+
+fn read(content: Vec<u8>, format: &str) -> Result<Value, Error> {
+    let value = match format {
+        "text" => Value::String(String::from_utf8_lossy(&content).to_string()),
+        "json" => from_slice(&content)?,
+        "yaml" => serde_yaml::from_slice(&content).map_err(|e| err!("108", e.to_string()))?,
+        "csv" => {
+            let mut reader = csv::Reader::from_reader(content.as_slice());
+            let header = reader
+                .headers()
+                .map_err(|e| err!("108", e.to_string()))?
+                .clone();
+            let mut rows = Vec::new();
+            for record in reader.records() {
+                let record = record.map_err(|e| err!("108", e.to_string()))?;
+                let row: Map = header
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(h, v)| (h.to_owned(), Value::String(v.to_owned())))
+                    .collect();
+                rows.push(Value::Object(row));
+            }
+            Value::Array(rows)
+        }
+        "base64" => Value::String(base64::encode(&content)),
+        _ => return Err(err!("107", format!("illegal format {}", format))),
+    };
+    Ok(value)
+}
+
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\\') {
+        return Err(err!("104", "forbidden access"));
+    }
+    Ok(())
+}
+
+fn path_create(mut file: PathBuf, arg: &dyn RunArg, path: &Value) -> Result<PathBuf, Error> {
+    let pav: Vec<&str> = path
+        .as_array()
+        .ok_or(err!("103", "missing path"))?
+        .iter()
+        .filter_map(|p| p.as_str())
+        .collect();
+
+    if pav.is_empty() {
+        return Err(err!("103", "missing path"));
+    }
+    if !check_path(arg.id().case_id().task_id().to_string().as_str(), &pav) {
+        return Err(err!("104", "forbidden access"));
+    }
+    for pa in pav {
+        file = file.join(pa);
+    }
+    Ok(file)
+}
+
+#[test]
+fn read_test() {
+    let rows = read(b"id,name\n1,a\n2,b\n".to_vec(), "csv").unwrap();
+    assert_eq!(
+        json!([{"id": "1", "name": "a"}, {"id": "2", "name": "b"}]),
+        rows
+    );
+
+    let yaml = content_create(&json!({"a": [1, 2]}), "yaml").unwrap();
+    assert_eq!(json!({"a": [1, 2]}), read(yaml, "yaml").unwrap());
+
+    let bytes = content_create(&json!("aGVsbG8="), "base64").unwrap();
+    assert_eq!(json!("aGVsbG8="), read(bytes, "base64").unwrap());
+
+    assert_eq!("104", check_name("..").unwrap_err().code());
+}
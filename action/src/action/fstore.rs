@@ -6,6 +6,8 @@ use log::trace;
 use chord::action::prelude::*;
 use chord::value::{Map, Number};
 
+use crate::action::workdir::check_path;
+
 pub struct FstoreFactory {
     workdir: PathBuf,
 }
@@ -61,26 +63,23 @@ impl Action for Fstore {
 
 async fn run0(fstore: &Fstore, arg: &dyn RunArg) -> std::result::Result<Value, Error> {
     let args = arg.args();
-    let pav: Vec<String> = args["path"]
+    let pav: Vec<&str> = args["path"]
         .as_array()
         .ok_or(err!("102", "missing path"))?
         .iter()
-        .map(|p| p.as_str())
-        .filter(|p| p.is_some())
-        .map(|p| p.unwrap())
-        .map(|p| p.to_owned())
+        .filter_map(|p| p.as_str())
         .collect();
 
-    if pav.len() < 1 {
+    if pav.is_empty() {
         return Err(err!("103", "missing path"));
     }
-    if !pav[0].starts_with(arg.id().case_id().task_id().to_string().as_str()) {
+    if !check_path(arg.id().case_id().task_id().to_string().as_str(), &pav) {
         return Err(err!("104", "forbidden access"));
     }
 
     let mut path_src = fstore.tmp.parent().unwrap().to_path_buf();
     for pa in pav {
-        path_src = path_src.join(pa.as_str());
+        path_src = path_src.join(pa);
     }
     let path_dest = fstore.tmp.join(arg.id().to_string());
 
@@ -0,0 +1,265 @@
+use std::future::Future;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+use surf::http::Method;
+use surf::{Client, RequestBuilder, Url};
+
+use chord::action::prelude::*;
+use chord::value::{from_slice, Map};
+
+use crate::action::http;
+use crate::action::http::client::ClientPool;
+use crate::action::http::cookie;
+
+/// error code of apollo automatic persisted queries when the server does not know the hash
+const PERSISTED_QUERY_NOT_FOUND: &str = "PERSISTED_QUERY_NOT_FOUND";
+
+pub struct GraphqlFactory {
+    pool: ClientPool,
+}
+
+impl GraphqlFactory {
+    pub async fn new(config: Option<Value>) -> Result<GraphqlFactory, Error> {
+        let pool = ClientPool::new(config.as_ref())?;
+        Ok(GraphqlFactory { pool })
+    }
+}
+
+#[async_trait]
+impl Factory for GraphqlFactory {
+    async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let client = match arg.args()["client"].as_str() {
+            Some(name) => self.pool.get(Some(arg.render_str(name)?.as_str()))?,
+            None => self.pool.get(None)?,
+        };
+        Ok(Box::new(Graphql { client }))
+    }
+}
+
+struct Graphql {
+    client: Client,
+}
+
+#[async_trait]
+impl Action for Graphql {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let value = run0(self, arg).await.map_err(|e| e.0)?;
+        Ok(Box::new(value))
+    }
+}
+
+/// ```yaml
+/// url: http://127.0.0.1:8080/graphql
+/// query: "query user($id: ID!) { user(id: $id) { name } }"
+/// variables: { id: "{{case.id}}" }
+/// operation_name: user
+/// persisted: true
+/// ```
+///
+/// `persisted: true` sends the sha256 hash of the query first, and the query itself
+/// only when the server answers `PERSISTED_QUERY_NOT_FOUND`,
+/// `hash` sends a known hash, `query` can then be omitted.
+/// with `errors` the step fails with the `extensions.code` of the first error, `graphql` without one,
+/// and all the errors as the message
+async fn run0(graphql: &Graphql, arg: &dyn RunArg) -> Result<Value, GraphqlError> {
+    let args = arg.args();
+
+    let url = args["url"].as_str().ok_or(err!("100", "missing url"))?;
+    let url = Url::from_str(url).or(Err(err!("101", format!("invalid url: {}", url))))?;
+
+    let query = args["query"].as_str();
+    let hash = match (args["hash"].as_str(), query) {
+        (Some(h), _) => Some(h.to_owned()),
+        (None, Some(q)) if args["persisted"].as_bool().unwrap_or(false) => {
+            Some(format!("{:x}", Sha256::digest(q.as_bytes())))
+        }
+        (None, Some(_)) => None,
+        (None, None) => return Err(err!("102", "missing query"))?,
+    };
+
+    let mut body = Map::new();
+    if !args["variables"].is_null() {
+        body.insert("variables".into(), args["variables"].clone());
+    }
+    if let Some(op) = args["operation_name"].as_str() {
+        body.insert("operationName".into(), Value::String(op.to_owned()));
+    }
+
+    let res = request(body, query, hash, |body| send(graphql, arg, &url, body)).await?;
+    Ok(data(res)?)
+}
+
+/// with a `hash` the query is sent only when the server answers `PERSISTED_QUERY_NOT_FOUND`
+async fn request<F, Fut>(
+    mut body: Map,
+    query: Option<&str>,
+    hash: Option<String>,
+    mut send: F,
+) -> Result<Value, GraphqlError>
+where
+    F: FnMut(Map) -> Fut,
+    Fut: Future<Output = Result<Value, GraphqlError>>,
+{
+    match hash {
+        Some(hash) => {
+            body.insert(
+                "extensions".into(),
+                json!({
+                    "persistedQuery": {
+                        "version": 1,
+                        "sha256Hash": hash
+                    }
+                }),
+            );
+            let res = send(body.clone()).await?;
+            match query {
+                Some(q) if error_code(&res) == Some(PERSISTED_QUERY_NOT_FOUND) => {
+                    body.insert("query".into(), Value::String(q.to_owned()));
+                    send(body).await
+                }
+                _ => Ok(res),
+            }
+        }
+        None => {
+            body.insert(
+                "query".into(),
+                Value::String(query.unwrap_or("").to_owned()),
+            );
+            send(body).await
+        }
+    }
+}
+
+/// `data`, or the first error's `extensions.code` with every error as the message
+fn data(mut res: Value) -> Result<Value, Error> {
+    if let Some(errors) = res["errors"].as_array() {
+        if !errors.is_empty() {
+            let code = error_code(&res).unwrap_or("graphql");
+            return Err(err!(code, res["errors"].to_string()));
+        }
+    }
+    Ok(res["data"].take())
+}
+
+async fn send(
+    graphql: &Graphql,
+    arg: &dyn RunArg,
+    url: &Url,
+    body: Map,
+) -> Result<Value, GraphqlError> {
+    let mut rb = RequestBuilder::new(Method::Post, url.clone());
+    rb = http::header_apply(rb, &arg.args()["header"])?;
+    rb = rb.body(Value::Object(body));
+
+    let session = cookie::session(arg)?;
+    let mut req = rb.build();
+    cookie::apply(session, &mut req, url);
+
+    let mut res = graphql.client.send(req).await?;
+    if let Some(s) = session {
+        cookie::store(s, url, &res);
+    }
+
+    let status = res.status();
+    let bytes = res.body_bytes().await?;
+    // a graphql server may answer errors with a non 2xx status, the body still tells why
+    match from_slice::<Value>(bytes.as_slice()) {
+        Ok(v) if v.is_object() => Ok(v),
+        _ => Err(err!("108", format!("invalid response, status {}", status)))?,
+    }
+}
+
+/// `extensions.code` of the first error
+fn error_code(res: &Value) -> Option<&str> {
+    res["errors"][0]["extensions"]["code"].as_str()
+}
+
+struct GraphqlError(chord::Error);
+
+impl From<surf::Error> for GraphqlError {
+    fn from(err: surf::Error) -> GraphqlError {
+        GraphqlError(err!("107", format!("{}", err.status())))
+    }
+}
+
+impl From<chord::Error> for GraphqlError {
+    fn from(err: Error) -> Self {
+        GraphqlError(err)
+    }
+}
+
+#[test]
+fn data_test() {
+    assert_eq!(
+        json!({"user": {"name": "a"}}),
+        data(json!({"data": {"user": {"name": "a"}}})).unwrap()
+    );
+    assert_eq!(
+        json!({"user": null}),
+        data(json!({"data": {"user": null}, "errors": []})).unwrap()
+    );
+
+    let e = data(json!({"errors": [
+        {"message": "a", "extensions": {"code": "UNAUTHENTICATED"}},
+        {"message": "b", "extensions": {"code": "FORBIDDEN"}}
+    ]}))
+    .unwrap_err();
+    assert_eq!("UNAUTHENTICATED", e.code());
+    assert!(e.message().contains("FORBIDDEN"));
+
+    let e = data(json!({"data": null, "errors": [{"message": "a"}]})).unwrap_err();
+    assert_eq!("graphql", e.code());
+}
+
+#[test]
+fn request_test() {
+    use std::cell::RefCell;
+
+    let not_found = json!({"errors": [{"message": "PersistedQueryNotFound",
+        "extensions": {"code": PERSISTED_QUERY_NOT_FOUND}}]});
+    let ok = json!({"data": {"a": 1}});
+    let run = |query: Option<&str>, hash: Option<&str>, answers: Vec<Value>| {
+        let sent = RefCell::new(vec![]);
+        let answers = RefCell::new(answers);
+        let res = async_std::task::block_on(request(
+            Map::new(),
+            query,
+            hash.map(str::to_owned),
+            |body| {
+                sent.borrow_mut().push(Value::Object(body));
+                let answer = answers.borrow_mut().remove(0);
+                async move { Ok(answer) }
+            },
+        ))
+        .map_err(|e| e.0)
+        .unwrap();
+        (res, sent.into_inner())
+    };
+
+    let (res, sent) = run(Some("{a}"), Some("h"), vec![not_found.clone(), ok.clone()]);
+    assert_eq!(ok, res);
+    assert_eq!(2, sent.len());
+    assert!(sent[0]["query"].is_null());
+    assert_eq!(
+        json!("h"),
+        sent[0]["extensions"]["persistedQuery"]["sha256Hash"]
+    );
+    assert_eq!(json!("{a}"), sent[1]["query"]);
+    assert_eq!(
+        json!("h"),
+        sent[1]["extensions"]["persistedQuery"]["sha256Hash"]
+    );
+
+    let (res, sent) = run(Some("{a}"), Some("h"), vec![ok.clone()]);
+    assert_eq!(ok, res);
+    assert_eq!(1, sent.len());
+
+    let (res, sent) = run(None, Some("h"), vec![not_found.clone()]);
+    assert_eq!(not_found, res);
+    assert_eq!(1, sent.len());
+
+    let (res, sent) = run(Some("{a}"), None, vec![ok.clone()]);
+    assert_eq!(ok, res);
+    assert_eq!(json!({"query": "{a}"}), sent[0]);
+}
@@ -0,0 +1,625 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use futures::stream;
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, MethodDescriptor};
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder, Streaming};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::{Ascii, Binary, KeyAndValueRef, MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Status};
+use tonic_reflection::pb::server_reflection_client::ServerReflectionClient;
+use tonic_reflection::pb::server_reflection_request::MessageRequest;
+use tonic_reflection::pb::server_reflection_response::MessageResponse;
+use tonic_reflection::pb::ServerReflectionRequest;
+
+use chord::action::prelude::*;
+use chord::value::Map;
+
+/// ```yaml
+/// grpc:
+///     descriptor:
+///         - /data/chord/proto/helloworld.pb
+///     proto:
+///         include:
+///             - /data/chord/proto
+///         file:
+///             - helloworld.proto
+/// ```
+///
+/// `descriptor` are file descriptor sets, `proto` files are compiled by `protoc`
+pub struct GrpcFactory {
+    pool: DescriptorPool,
+}
+
+impl GrpcFactory {
+    pub async fn new(config: Option<Value>) -> Result<GrpcFactory, Error> {
+        let mut pool = DescriptorPool::new();
+        let config = match config {
+            Some(c) => c,
+            None => return Ok(GrpcFactory { pool }),
+        };
+
+        if let Some(descriptor) = config["descriptor"].as_array() {
+            for path in descriptor.iter().filter_map(|p| p.as_str()) {
+                let bytes = std::fs::read(path)?;
+                pool.decode_file_descriptor_set(bytes.as_slice())
+                    .map_err(|e| err!("descriptor", format!("{} {}", path, e)))?;
+            }
+        }
+
+        if let Some(file) = config["proto"]["file"].as_array() {
+            let bytes = protoc(&config["proto"]["include"], file)?;
+            pool.decode_file_descriptor_set(bytes.as_slice())
+                .map_err(|e| err!("descriptor", e.to_string()))?;
+        }
+
+        Ok(GrpcFactory { pool })
+    }
+}
+
+#[async_trait]
+impl Factory for GrpcFactory {
+    async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let url = arg.args()["url"]
+            .as_str()
+            .map(|s| arg.render_str(s))
+            .ok_or(err!("100", "missing url"))??;
+
+        let channel = if arg.is_shared(url.as_str()) {
+            Some(endpoint(url.as_str())?.connect_lazy())
+        } else {
+            None
+        };
+
+        Ok(Box::new(Grpc {
+            pool: self.pool.clone(),
+            channel,
+            reflected: Mutex::new(HashMap::new()),
+        }))
+    }
+}
+
+struct Grpc {
+    pool: DescriptorPool,
+    channel: Option<Channel>,
+    /// methods resolved by server reflection, keyed by path
+    reflected: Mutex<HashMap<String, MethodDescriptor>>,
+}
+
+#[async_trait]
+impl Action for Grpc {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let channel = match self.channel.as_ref() {
+            Some(c) => c.clone(),
+            None => {
+                let url = arg.args()["url"]
+                    .as_str()
+                    .ok_or(err!("100", "missing url"))?;
+                endpoint(url)?
+                    .connect()
+                    .await
+                    .map_err(|e| err!("106", e.to_string()))?
+            }
+        };
+        let value = run0(self, arg.args(), channel).await?;
+        Ok(Box::new(value))
+    }
+}
+
+/// ```yaml
+/// url: http://127.0.0.1:50051
+/// service: helloworld.Greeter
+/// method: SayHello
+/// metadata:
+///     x-token: "{{case.token}}"
+/// body:
+///     name: "{{case.name}}"
+/// ```
+///
+/// methods unknown to the configured descriptors are resolved by server reflection
+async fn run0(grpc: &Grpc, args: &Value, channel: Channel) -> Result<Value, Error> {
+    let service = args["service"]
+        .as_str()
+        .ok_or(err!("102", "missing service"))?;
+    let method = args["method"]
+        .as_str()
+        .ok_or(err!("103", "missing method"))?;
+
+    let md = method_find(grpc, channel.clone(), service, method).await?;
+    if md.is_client_streaming() {
+        return Err(err!("109", "client streaming is not supported"));
+    }
+
+    let body = if args["body"].is_null() {
+        Value::Object(Map::new())
+    } else {
+        args["body"].clone()
+    };
+    let message = DynamicMessage::deserialize(md.input(), body)
+        .map_err(|e| err!("105", format!("invalid body: {}", e)))?;
+
+    let mut request = Request::new(message);
+    if let Some(metadata) = args["metadata"].as_object() {
+        metadata_apply(request.metadata_mut(), metadata)?;
+    }
+
+    let path = PathAndQuery::from_str(format!("/{}/{}", service, method).as_str())
+        .map_err(|_| err!("103", "invalid method"))?;
+    let codec = DynamicCodec {
+        output: md.output(),
+    };
+
+    let mut client = tonic::client::Grpc::new(channel);
+    client
+        .ready()
+        .await
+        .map_err(|e| err!("106", e.to_string()))?;
+
+    if md.is_server_streaming() {
+        let res = client.server_streaming(request, path, codec).await;
+        match res {
+            Ok(res) => {
+                let (metadata, stream, _) = res.into_parts();
+                stream_collect(metadata, stream).await
+            }
+            Err(status) => Ok(status_value(&status)),
+        }
+    } else {
+        let res = client.unary(request, path, codec).await;
+        match res {
+            Ok(res) => {
+                let (metadata, message, _) = res.into_parts();
+                Ok(json!({
+                    "status": 0,
+                    "message": "",
+                    "metadata": metadata_value(&metadata),
+                    "body": to_value(&message)?
+                }))
+            }
+            Err(status) => Ok(status_value(&status)),
+        }
+    }
+}
+
+async fn stream_collect(
+    metadata: MetadataMap,
+    mut stream: Streaming<DynamicMessage>,
+) -> Result<Value, Error> {
+    let mut body = vec![];
+    loop {
+        match stream.message().await {
+            Ok(Some(message)) => body.push(to_value(&message)?),
+            Ok(None) => break,
+            Err(status) => {
+                let mut value = status_value(&status);
+                value["body"] = Value::Array(body);
+                return Ok(value);
+            }
+        }
+    }
+    let mut metadata = metadata_value(&metadata);
+    if let Ok(Some(trailers)) = stream.trailers().await {
+        if let (Some(m), Value::Object(t)) = (metadata.as_object_mut(), metadata_value(&trailers)) {
+            m.extend(t);
+        }
+    }
+    Ok(json!({
+        "status": 0,
+        "message": "",
+        "metadata": metadata,
+        "body": body
+    }))
+}
+
+async fn method_find(
+    grpc: &Grpc,
+    channel: Channel,
+    service: &str,
+    method: &str,
+) -> Result<MethodDescriptor, Error> {
+    if let Some(md) = method_of(&grpc.pool, service, method) {
+        return Ok(md);
+    }
+
+    let path = format!("{}/{}", service, method);
+    if let Some(md) = grpc.reflected.lock().unwrap().get(&path) {
+        return Ok(md.clone());
+    }
+
+    let pool = reflect(channel, service).await?;
+    let md =
+        method_of(&pool, service, method).ok_or(err!("104", format!("unknown method {}", path)))?;
+    grpc.reflected.lock().unwrap().insert(path, md.clone());
+    Ok(md)
+}
+
+fn method_of(pool: &DescriptorPool, service: &str, method: &str) -> Option<MethodDescriptor> {
+    pool.get_service_by_name(service)?
+        .methods()
+        .find(|m| m.name() == method)
+}
+
+/// asks the server reflection service for the file declaring `service`, with its dependencies
+async fn reflect(channel: Channel, service: &str) -> Result<DescriptorPool, Error> {
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::FileContainingSymbol(service.to_owned())),
+    };
+
+    let mut client = ServerReflectionClient::new(channel);
+    let mut stream = client
+        .server_reflection_info(stream::iter(vec![request]))
+        .await
+        .map_err(|s| err!("108", format!("reflection {}", s.message())))?
+        .into_inner();
+
+    let response = stream
+        .message()
+        .await
+        .map_err(|s| err!("108", format!("reflection {}", s.message())))?
+        .ok_or(err!("108", "reflection no response"))?;
+
+    let files = match response.message_response {
+        Some(MessageResponse::FileDescriptorResponse(r)) => r.file_descriptor_proto,
+        Some(MessageResponse::ErrorResponse(e)) => {
+            return Err(err!("108", format!("reflection {}", e.error_message)))
+        }
+        _ => return Err(err!("108", "reflection unexpected response")),
+    };
+
+    let mut protos = vec![];
+    for file in files {
+        let proto = prost_types::FileDescriptorProto::decode(file.as_slice())
+            .map_err(|e| err!("108", format!("reflection {}", e)))?;
+        protos.push(proto);
+    }
+    let mut pool = DescriptorPool::new();
+    pool.add_file_descriptor_protos(protos)
+        .map_err(|e| err!("108", format!("reflection {}", e)))?;
+    Ok(pool)
+}
+
+/// keys ending with `-bin` take base64 values
+fn metadata_apply(metadata: &mut MetadataMap, value: &Map) -> Result<(), Error> {
+    for (k, v) in value.iter() {
+        let v = match v {
+            Value::String(s) => s.clone(),
+            _ => v.to_string(),
+        };
+        if k.ends_with("-bin") {
+            let key = MetadataKey::<Binary>::from_bytes(k.as_bytes())
+                .or(Err(err!("107", format!("invalid metadata key {}", k))))?;
+            let bytes = base64::decode(v.as_str())
+                .or(Err(err!("107", format!("invalid metadata value {}", k))))?;
+            metadata.append_bin(key, MetadataValue::from_bytes(bytes.as_slice()));
+        } else {
+            let key = MetadataKey::<Ascii>::from_bytes(k.as_bytes())
+                .or(Err(err!("107", format!("invalid metadata key {}", k))))?;
+            let value = MetadataValue::try_from(v.as_str())
+                .or(Err(err!("107", format!("invalid metadata value {}", k))))?;
+            metadata.append(key, value);
+        }
+    }
+    Ok(())
+}
+
+fn metadata_value(metadata: &MetadataMap) -> Value {
+    let mut map = Map::new();
+    for kv in metadata.iter() {
+        let (k, v) = match kv {
+            KeyAndValueRef::Ascii(k, v) => (
+                k.to_string(),
+                Value::String(v.to_str().unwrap_or("").to_owned()),
+            ),
+            KeyAndValueRef::Binary(k, v) => (
+                k.to_string(),
+                Value::String(v.to_bytes().map(base64::encode).unwrap_or_default()),
+            ),
+        };
+        match map.get_mut(&k) {
+            Some(Value::Array(vs)) => vs.push(v),
+            _ => {
+                map.insert(k, Value::Array(vec![v]));
+            }
+        }
+    }
+    Value::Object(map)
+}
+
+fn status_value(status: &Status) -> Value {
+    json!({
+        "status": status.code() as i32,
+        "message": status.message(),
+        "metadata": metadata_value(status.metadata()),
+        "body": Value::Null
+    })
+}
+
+fn endpoint(url: &str) -> Result<Endpoint, Error> {
+    Endpoint::from_shared(url.to_owned()).or(Err(err!("101", format!("invalid url: {}", url))))
+}
+
+/// numbers the descriptor sets of one process
+static PROTOC_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+fn protoc(include: &Value, file: &[Value]) -> Result<Vec<u8>, Error> {
+    let out = std::env::temp_dir().join(format!(
+        "chord-grpc-{}-{}.pb",
+        std::process::id(),
+        PROTOC_SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut cmd = std::process::Command::new("protoc");
+    cmd.arg("--include_imports")
+        .arg(format!("--descriptor_set_out={}", out.to_string_lossy()));
+    if let Some(include) = include.as_array() {
+        for i in include.iter().filter_map(|i| i.as_str()) {
+            cmd.arg(format!("--proto_path={}", i));
+        }
+    }
+    for f in file.iter().filter_map(|f| f.as_str()) {
+        cmd.arg(f);
+    }
+
+    let output = cmd.output();
+    let bytes = match output {
+        Ok(output) if output.status.success() => std::fs::read(&out).map_err(Error::from),
+        Ok(output) => Err(err!(
+            "protoc",
+            String::from_utf8_lossy(&output.stderr).to_string()
+        )),
+        Err(e) => Err(e.into()),
+    };
+    let _ = std::fs::remove_file(&out);
+    bytes
+}
+
+/// encodes and decodes `DynamicMessage` with the descriptor of the method
+struct DynamicCodec {
+    output: MessageDescriptor,
+}
+
+impl Codec for DynamicCodec {
+    type Encode = DynamicMessage;
+    type Decode = DynamicMessage;
+    type Encoder = DynamicEncoder;
+    type Decoder = DynamicDecoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        DynamicEncoder
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        DynamicDecoder(self.output.clone())
+    }
+}
+
+struct DynamicEncoder;
+
+impl Encoder for DynamicEncoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        item.encode(dst)
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+struct DynamicDecoder(MessageDescriptor);
+
+impl Decoder for DynamicDecoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        DynamicMessage::decode(self.0.clone(), src)
+            .map(Some)
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+#[test]
+fn grpc_test() {
+    use std::convert::Infallible;
+    use std::future::{ready, Ready};
+
+    use prost_reflect::Value as ProtoValue;
+    use prost_types::field_descriptor_proto::{Label, Type};
+    use prost_types::{
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, MethodDescriptorProto,
+        ServiceDescriptorProto,
+    };
+    use tonic::body::BoxBody;
+    use tonic::codegen::{
+        empty_body, http, tokio_stream, Body, BoxFuture, Context, Poll, Service, StdError,
+    };
+    use tonic::server::{NamedService, ServerStreamingService, UnaryService};
+    use tonic::Response;
+
+    fn message(name: &str, field: &str) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(name.into()),
+            field: vec![FieldDescriptorProto {
+                name: Some(field.into()),
+                json_name: Some(field.into()),
+                number: Some(1),
+                label: Some(Label::Optional as i32),
+                r#type: Some(Type::String as i32),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn method(name: &str, server_streaming: bool) -> MethodDescriptorProto {
+        MethodDescriptorProto {
+            name: Some(name.into()),
+            input_type: Some(".helloworld.HelloRequest".into()),
+            output_type: Some(".helloworld.HelloReply".into()),
+            server_streaming: Some(server_streaming),
+            ..Default::default()
+        }
+    }
+
+    fn reply(pool: &DescriptorPool, request: &DynamicMessage, n: usize) -> DynamicMessage {
+        let name = request
+            .get_field_by_name("name")
+            .and_then(|v| v.as_str().map(str::to_owned))
+            .unwrap_or_default();
+        let mut reply =
+            DynamicMessage::new(pool.get_message_by_name("helloworld.HelloReply").unwrap());
+        reply.set_field_by_name(
+            "message",
+            ProtoValue::String(format!("hello {} {}", name, n)),
+        );
+        reply
+    }
+
+    struct SayHello(DescriptorPool);
+
+    impl UnaryService<DynamicMessage> for SayHello {
+        type Response = DynamicMessage;
+        type Future = Ready<Result<Response<DynamicMessage>, Status>>;
+
+        fn call(&mut self, request: Request<DynamicMessage>) -> Self::Future {
+            ready(Ok(Response::new(reply(&self.0, request.get_ref(), 0))))
+        }
+    }
+
+    struct SayHellos(DescriptorPool);
+
+    impl ServerStreamingService<DynamicMessage> for SayHellos {
+        type Response = DynamicMessage;
+        type ResponseStream =
+            tokio_stream::Iter<std::vec::IntoIter<Result<DynamicMessage, Status>>>;
+        type Future = Ready<Result<Response<Self::ResponseStream>, Status>>;
+
+        fn call(&mut self, request: Request<DynamicMessage>) -> Self::Future {
+            let mut replies = Vec::new();
+            for n in 0..3 {
+                replies.push(Ok(reply(&self.0, request.get_ref(), n)));
+            }
+            ready(Ok(Response::new(tokio_stream::iter(replies))))
+        }
+    }
+
+    /// what `tonic-build` generates for the two methods, over `DynamicMessage`
+    #[derive(Clone)]
+    struct Greeter(DescriptorPool);
+
+    impl NamedService for Greeter {
+        const NAME: &'static str = "helloworld.Greeter";
+    }
+
+    impl<B> Service<http::Request<B>> for Greeter
+    where
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let pool = self.0.clone();
+            let mut grpc = tonic::server::Grpc::new(DynamicCodec {
+                output: pool.get_message_by_name("helloworld.HelloRequest").unwrap(),
+            });
+            match req.uri().path() {
+                "/helloworld.Greeter/SayHello" => {
+                    Box::pin(async move { Ok(grpc.unary(SayHello(pool), req).await) })
+                }
+                "/helloworld.Greeter/SayHellos" => {
+                    Box::pin(async move { Ok(grpc.server_streaming(SayHellos(pool), req).await) })
+                }
+                _ => Box::pin(async move {
+                    Ok(http::Response::builder()
+                        .status(200)
+                        .header("grpc-status", "12")
+                        .header("content-type", "application/grpc")
+                        .body(empty_body())
+                        .unwrap())
+                }),
+            }
+        }
+    }
+
+    let mut pool = DescriptorPool::new();
+    pool.add_file_descriptor_proto(FileDescriptorProto {
+        name: Some("helloworld.proto".into()),
+        package: Some("helloworld".into()),
+        message_type: vec![
+            message("HelloRequest", "name"),
+            message("HelloReply", "message"),
+        ],
+        service: vec![ServiceDescriptorProto {
+            name: Some("Greeter".into()),
+            method: vec![method("SayHello", false), method("SayHellos", true)],
+            ..Default::default()
+        }],
+        syntax: Some("proto3".into()),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let server = tonic::transport::Server::builder()
+        .add_service(Greeter(pool.clone()))
+        .serve(addr);
+    async_std::task::spawn(server);
+
+    let grpc = Grpc {
+        pool,
+        channel: None,
+        reflected: Mutex::new(HashMap::new()),
+    };
+    async_std::task::block_on(async {
+        let url = format!("http://{}", addr);
+        let mut channel = endpoint(url.as_str()).unwrap().connect().await;
+        for _ in 0..50 {
+            if channel.is_ok() {
+                break;
+            }
+            async_std::task::sleep(std::time::Duration::from_millis(100)).await;
+            channel = endpoint(url.as_str()).unwrap().connect().await;
+        }
+        let channel = channel.unwrap();
+
+        let args = json!({
+            "service": "helloworld.Greeter",
+            "method": "SayHello",
+            "body": {"name": "foo"}
+        });
+        let value = run0(&grpc, &args, channel.clone()).await.unwrap();
+        assert_eq!(0, value["status"]);
+        assert_eq!(json!({"message": "hello foo 0"}), value["body"]);
+
+        let args = json!({
+            "service": "helloworld.Greeter",
+            "method": "SayHellos",
+            "body": {"name": "bar"}
+        });
+        let value = run0(&grpc, &args, channel).await.unwrap();
+        assert_eq!(0, value["status"]);
+        assert_eq!(
+            json!([
+                {"message": "hello bar 0"},
+                {"message": "hello bar 1"},
+                {"message": "hello bar 2"}
+            ]),
+            value["body"]
+        );
+    });
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use http_client::isahc::IsahcClient;
+use isahc::config::{
+    CaCertificate, ClientCertificate, Configurable, PrivateKey, SslOption, VersionNegotiation,
+};
+use isahc::http::Uri;
+use isahc::HttpClientBuilder;
+use surf::Client;
+
+use chord::err;
+use chord::value::Value;
+use chord::Error;
+
+/// named `surf::Client` profiles, built once per factory from `<action>.client`
+pub struct ClientPool {
+    default: Client,
+    profile: HashMap<String, Client>,
+}
+
+impl ClientPool {
+    pub fn new(config: Option<&Value>) -> Result<ClientPool, Error> {
+        let client_config = config.map(|c| &c["client"]).unwrap_or(&Value::Null);
+
+        let mut profile = HashMap::new();
+        if let Some(map) = client_config.as_object() {
+            for (name, conf) in map {
+                profile.insert(name.to_owned(), client_create(name, conf)?);
+            }
+        }
+
+        let default = match profile.get("default") {
+            Some(c) => c.clone(),
+            None => client_create("default", &Value::Null)?,
+        };
+
+        Ok(ClientPool { default, profile })
+    }
+
+    pub fn get(&self, name: Option<&str>) -> Result<Client, Error> {
+        match name {
+            None => Ok(self.default.clone()),
+            Some(name) => self
+                .profile
+                .get(name)
+                .cloned()
+                .ok_or(err!("client", format!("unknown client {}", name))),
+        }
+    }
+}
+
+fn client_create(name: &str, conf: &Value) -> Result<Client, Error> {
+    // restapi reads the per-phase timings from the response extensions
+    let mut builder = HttpClientBuilder::new().metrics(true);
+
+    if let Some(size) = conf["pool_size"].as_u64() {
+        builder = builder
+            .max_connections(size as usize)
+            .connection_cache_size(size as usize);
+    }
+
+    if let Some(size) = conf["pool_size_per_host"].as_u64() {
+        builder = builder.max_connections_per_host(size as usize);
+    }
+
+    match &conf["keep_alive"] {
+        Value::Bool(false) => {
+            builder = builder.connection_cache_size(0);
+        }
+        Value::Number(sec) => {
+            let sec = Duration::from_secs(sec.as_u64().unwrap_or(0));
+            builder = builder.connection_cache_ttl(sec).tcp_keepalive(sec);
+        }
+        _ => {}
+    }
+
+    if let Some(sec) = conf["connect_timeout"].as_u64() {
+        builder = builder.connect_timeout(Duration::from_secs(sec));
+    }
+
+    if let Some(sec) = conf["timeout"].as_u64() {
+        builder = builder.timeout(Duration::from_secs(sec));
+    }
+
+    if let Some(proxy) = conf["proxy"].as_str() {
+        let proxy =
+            Uri::from_str(proxy).or(Err(err!("client", format!("{} invalid proxy", name))))?;
+        builder = builder.proxy(Some(proxy));
+    }
+
+    match conf["http2"].as_bool() {
+        Some(true) => builder = builder.version_negotiation(VersionNegotiation::http2()),
+        Some(false) => builder = builder.version_negotiation(VersionNegotiation::http11()),
+        None => {}
+    }
+
+    let tls = &conf["tls"];
+    if let Some(ca) = tls["ca"].as_str() {
+        builder = builder.ssl_ca_certificate(CaCertificate::file(ca));
+    }
+
+    if let Some(cert) = tls["cert"].as_str() {
+        let key = tls["key"]
+            .as_str()
+            .map(|k| PrivateKey::pem_file(k, tls["password"].as_str().map(|p| p.to_owned())));
+        builder = builder.ssl_client_certificate(ClientCertificate::pem_file(cert, key));
+    }
+
+    if tls["insecure"].as_bool().unwrap_or(false) {
+        builder = builder.ssl_options(
+            SslOption::DANGER_ACCEPT_INVALID_CERTS | SslOption::DANGER_ACCEPT_INVALID_HOSTS,
+        );
+    }
+
+    let client = builder
+        .build()
+        .map_err(|e| err!("client", format!("{} {}", name, e)))?;
+    Ok(Client::with_http_client(IsahcClient::from_client(client)))
+}
@@ -0,0 +1,282 @@
+use std::time::SystemTime;
+
+use surf::http::cookies::Cookie;
+use surf::{Request, Response, Url};
+
+use chord::action::{RunArg, Session};
+use chord::err;
+use chord::value::{json, Map, Value};
+use chord::Error;
+
+/// `session: case | task`, cookies are kept in the session under `cookies`, keyed by domain, path and name
+pub fn session(arg: &dyn RunArg) -> Result<Option<&dyn Session>, Error> {
+    match arg.args()["session"].as_str() {
+        None => Ok(None),
+        Some("case") => Ok(Some(arg.case_session())),
+        Some("task") => Ok(Some(arg.task_session())),
+        Some(s) => Err(err!("session", format!("unsupported session {}", s))),
+    }
+}
+
+/// `Cookie` header value for the url, or `None` if nothing matches
+pub fn header(session: &dyn Session, url: &Url) -> Option<String> {
+    jar_header(&session.get("cookies"), url, now())
+}
+
+/// adds the matching cookies to the `Cookie` header of `req`, after those of the step
+pub fn apply(session: Option<&dyn Session>, req: &mut Request, url: &Url) {
+    if let Some(cookie) = session.and_then(|s| header(s, url)) {
+        header_merge(req, cookie);
+    }
+}
+
+fn header_merge(req: &mut Request, cookie: String) {
+    let cookie = match req.header("Cookie") {
+        Some(hv) => format!("{}; {}", hv.last().as_str(), cookie),
+        None => cookie,
+    };
+    req.insert_header("Cookie", cookie);
+}
+
+/// store every `Set-Cookie` of the response
+pub fn store(session: &dyn Session, url: &Url, res: &Response) {
+    let set_cookie: Vec<String> = match res.header("Set-Cookie") {
+        Some(hv) => hv.iter().map(|v| v.to_string()).collect(),
+        None => return,
+    };
+    let now = now();
+    session.update("cookies", &mut |jar| {
+        jar_store(jar, url, set_cookie.as_slice(), now)
+    });
+}
+
+/// every matching cookie, longer paths first
+fn jar_header(jar: &Value, url: &Url, now: i64) -> Option<String> {
+    let jar = jar.as_object()?;
+    let mut cookies: Vec<&Value> = jar.values().filter(|c| matches(c, url, now)).collect();
+    cookies.sort_by_key(|c| std::cmp::Reverse(c["path"].as_str().map_or(0, str::len)));
+    let pairs: Vec<String> = cookies
+        .iter()
+        .map(|c| {
+            format!(
+                "{}={}",
+                c["name"].as_str().unwrap_or(""),
+                c["value"].as_str().unwrap_or("")
+            )
+        })
+        .collect();
+    if pairs.is_empty() {
+        None
+    } else {
+        Some(pairs.join("; "))
+    }
+}
+
+/// entries are keyed by domain, path and name, as a cookie is identified
+fn jar_store(jar: &mut Value, url: &Url, set_cookie: &[String], now: i64) {
+    if !jar.is_object() {
+        *jar = Value::Object(Map::new());
+    }
+    let jar = jar.as_object_mut().unwrap();
+    let host = url.host_str().unwrap_or("").to_lowercase();
+    for sc in set_cookie.iter() {
+        let cookie = match Cookie::parse(sc.as_str()) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+
+        let (domain, host_only) = match cookie.domain() {
+            Some(d) => (d.trim_start_matches('.').to_lowercase(), false),
+            None => (host.clone(), true),
+        };
+        if !host_only && !domain_store(host.as_str(), domain.as_str()) {
+            continue;
+        }
+        let path = cookie
+            .path()
+            .map(|p| p.to_owned())
+            .unwrap_or_else(|| default_path(url));
+        let key = format!("{};{};{}", domain, path, cookie.name());
+
+        let expires = match (cookie.max_age(), cookie.expires()) {
+            (Some(age), _) => Some(now + age.whole_seconds()),
+            (None, Some(exp)) => Some(exp.unix_timestamp()),
+            _ => None,
+        };
+        if expires.is_some_and(|e| e <= now) {
+            jar.remove(key.as_str());
+            continue;
+        }
+
+        jar.insert(
+            key,
+            json!({
+                "name": cookie.name(),
+                "value": cookie.value(),
+                "domain": domain,
+                "host_only": host_only,
+                "path": path,
+                "secure": cookie.secure().unwrap_or(false),
+                "http_only": cookie.http_only().unwrap_or(false),
+                "expires": expires
+            }),
+        );
+    }
+}
+
+fn matches(cookie: &Value, url: &Url, now: i64) -> bool {
+    if let Some(exp) = cookie["expires"].as_i64() {
+        if exp <= now {
+            return false;
+        }
+    }
+
+    if cookie["secure"].as_bool().unwrap_or(false) && url.scheme() != "https" {
+        return false;
+    }
+
+    let host = url.host_str().unwrap_or("").to_lowercase();
+    let domain = cookie["domain"].as_str().unwrap_or("");
+    let domain_ok = if cookie["host_only"].as_bool().unwrap_or(true) {
+        host == domain
+    } else {
+        domain_match(host.as_str(), domain)
+    };
+    if !domain_ok {
+        return false;
+    }
+
+    let path = cookie["path"].as_str().unwrap_or("/");
+    let req_path = url.path();
+    req_path == path
+        || (req_path.starts_with(path)
+            && (path.ends_with('/') || req_path[path.len()..].starts_with('/')))
+}
+
+/// rfc 6265 5.1.3, `host` is `domain` or a name under it
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// a `Domain` attribute is kept only if the host domain-matches it, and it is not a bare
+/// top level name like `com`, there is no public suffix list to check against
+fn domain_store(host: &str, domain: &str) -> bool {
+    domain_match(host, domain) && (host == domain || domain.contains('.'))
+}
+
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_owned(),
+        Some(i) => path[..i].to_owned(),
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[test]
+fn matches_test() {
+    let cookie = json!({
+        "value": "1",
+        "domain": "example.com",
+        "host_only": false,
+        "path": "/api",
+        "secure": false,
+        "expires": null
+    });
+    let url = |u: &str| Url::parse(u).unwrap();
+    assert!(matches(&cookie, &url("http://example.com/api"), 0));
+    assert!(matches(&cookie, &url("http://a.example.com/api/user"), 0));
+    assert!(!matches(&cookie, &url("http://example.com/apiv2"), 0));
+    assert!(!matches(&cookie, &url("http://badexample.com/api"), 0));
+    assert_eq!("/a", default_path(&url("http://example.com/a/b")));
+}
+
+#[test]
+fn jar_test() {
+    let url = |u: &str| Url::parse(u).unwrap();
+    let mut jar = Value::Null;
+    jar_store(
+        &mut jar,
+        &url("http://a.example.com/login"),
+        &["JSESSIONID=a; Path=/".to_owned()],
+        0,
+    );
+    jar_store(
+        &mut jar,
+        &url("http://b.example.com/login"),
+        &["JSESSIONID=b; Path=/".to_owned()],
+        0,
+    );
+    jar_store(
+        &mut jar,
+        &url("http://b.example.com/login"),
+        &["JSESSIONID=c; Path=/api; Domain=example.com".to_owned()],
+        0,
+    );
+    assert_eq!(3, jar.as_object().unwrap().len());
+
+    assert_eq!(
+        Some("JSESSIONID=a".to_owned()),
+        jar_header(&jar, &url("http://a.example.com/"), 0)
+    );
+    assert_eq!(
+        Some("JSESSIONID=c; JSESSIONID=b".to_owned()),
+        jar_header(&jar, &url("http://b.example.com/api/user"), 0)
+    );
+
+    jar_store(
+        &mut jar,
+        &url("http://a.example.com/"),
+        &["JSESSIONID=a; Path=/; Max-Age=0".to_owned()],
+        0,
+    );
+    assert_eq!(None, jar_header(&jar, &url("http://a.example.com/"), 0));
+
+    let mut jar = Value::Null;
+    for domain in ["other.com", "com", "le.com", "1.1"] {
+        jar_store(
+            &mut jar,
+            &url("http://example.com/"),
+            &[format!("a=1; Domain={}", domain)],
+            0,
+        );
+    }
+    jar_store(
+        &mut jar,
+        &url("http://10.1.1.1/"),
+        &["a=1; Domain=1.1.1".to_owned()],
+        0,
+    );
+    assert_eq!(None, jar.as_object().filter(|j| !j.is_empty()).cloned());
+    jar_store(
+        &mut jar,
+        &url("http://localhost/"),
+        &["a=1; Domain=localhost".to_owned()],
+        0,
+    );
+    assert_eq!(1, jar.as_object().unwrap().len());
+}
+
+#[test]
+fn header_merge_test() {
+    let mut req = surf::get("http://example.com/").build();
+    header_merge(&mut req, "a=1".to_owned());
+    assert_eq!("a=1", req.header("Cookie").unwrap().as_str());
+
+    let mut req = surf::get("http://example.com/")
+        .header("Cookie", "x=0")
+        .build();
+    header_merge(&mut req, "a=1; b=2".to_owned());
+    let cookie = req.header("Cookie").unwrap();
+    assert_eq!(1, cookie.iter().count());
+    assert_eq!("x=0; a=1; b=2", cookie.as_str());
+}
@@ -0,0 +1,43 @@
+use std::str::FromStr;
+
+use surf::http::headers::{HeaderName, HeaderValue};
+use surf::RequestBuilder;
+
+use chord::err;
+use chord::value::Value;
+use chord::Error;
+
+pub mod client;
+#[cfg(any(
+    feature = "act_restapi",
+    feature = "act_graphql",
+    feature = "act_download"
+))]
+pub mod cookie;
+
+/// `header: {name: value | [value]}`
+pub fn header_apply(mut rb: RequestBuilder, header: &Value) -> Result<RequestBuilder, Error> {
+    if let Some(header) = header.as_object() {
+        for (k, v) in header.iter() {
+            let hn =
+                HeaderName::from_string(k.clone()).or(Err(err!("104", "invalid header name")))?;
+            let hvs: Vec<HeaderValue> = match v {
+                Value::String(v) => {
+                    vec![HeaderValue::from_str(v).or(Err(err!("105", "invalid header value")))?]
+                }
+                Value::Array(vs) => {
+                    let mut vec = vec![];
+                    for v in vs {
+                        let v = HeaderValue::from_str(v.to_string().as_str())
+                            .or(Err(err!("105", "invalid header value")))?;
+                        vec.push(v)
+                    }
+                    vec
+                }
+                _ => return Err(err!("106", "invalid header value")),
+            };
+            rb = rb.header(hn, hvs.as_slice());
+        }
+    }
+    Ok(rb)
+}
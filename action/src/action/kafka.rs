@@ -0,0 +1,455 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_std::future::timeout;
+use async_std::sync::Mutex;
+use async_std::task::{sleep, spawn};
+use futures::channel::oneshot;
+use rdkafka::client::DefaultClientContext;
+use rdkafka::consumer::{Consumer as _, DefaultConsumerContext, StreamConsumer};
+use rdkafka::message::{Headers, Message, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::{AsyncRuntime, Timeout};
+use rdkafka::{ClientConfig, Offset, TopicPartitionList};
+
+use chord::action::prelude::*;
+use chord::value::from_str;
+
+/// pending messages a shared consumer keeps for cases still waiting
+const PENDING_MAX: usize = 1000;
+
+pub struct KafkaFactory {}
+
+impl KafkaFactory {
+    pub async fn new(_: Option<Value>) -> Result<KafkaFactory, Error> {
+        Ok(KafkaFactory {})
+    }
+}
+
+#[async_trait]
+impl Factory for KafkaFactory {
+    async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let args = arg.args();
+        let brokers = match args["brokers"].as_str() {
+            Some(b) if arg.is_shared(b) => arg.render_str(b)?,
+            _ => {
+                return Ok(Box::new(Kafka {
+                    producer: None,
+                    consumer: None,
+                }))
+            }
+        };
+
+        let mut config = client_config(brokers.as_str(), &args["config"]);
+        let produce = &args["produce"];
+        let producer = if produce.is_object()
+            && produce["partitioner"]
+                .as_str()
+                .into_iter()
+                .all(|p| arg.is_shared(p))
+        {
+            if let Some(p) = produce["partitioner"].as_str() {
+                config.set("partitioner", arg.render_str(p)?.as_str());
+            }
+            Some(producer_create(&config)?)
+        } else {
+            None
+        };
+
+        let consume = &args["consume"];
+        let topic = consume["topic"].as_str().filter(|t| arg.is_shared(t));
+        let group = consume["group"].as_str();
+        let consumer = match topic {
+            Some(topic) if group.into_iter().all(|g| arg.is_shared(g)) => {
+                let topic = arg.render_str(topic)?;
+                let group = match group {
+                    Some(g) => Some(arg.render_str(g)?),
+                    None => None,
+                };
+                let consumer = consumer_create(
+                    brokers.as_str(),
+                    &args["config"],
+                    topic.as_str(),
+                    group.as_deref(),
+                    consume["offset"].as_str(),
+                )
+                .await?;
+                Some(consumer)
+            }
+            _ => None,
+        };
+
+        Ok(Box::new(Kafka { producer, consumer }))
+    }
+}
+
+/// a producer or consumer with literal args is opened once, when the stage starts,
+/// so a consumer without `group` sees every message sent after that
+struct Kafka {
+    producer: Option<FutureProducer<DefaultClientContext, AsyncStdRuntime>>,
+    consumer: Option<Arc<Mutex<Pending>>>,
+}
+
+struct Pending {
+    consumer: Arc<StreamConsumer<DefaultConsumerContext, AsyncStdRuntime>>,
+    messages: VecDeque<(u64, Value)>,
+    seq: u64,
+}
+
+/// ```yaml
+/// brokers: 127.0.0.1:9092
+/// config:
+///     message.timeout.ms: 5000
+/// produce:
+///     topic: order
+///     key: "{{case.id}}"
+///     value: { "id": "{{case.id}}", "amount": 10 }
+///     headers:
+///         trace: "{{case.trace}}"
+///     partitioner: murmur2_random
+/// ```
+///
+/// `value` strings go raw, other values as json text, `partition` pins the partition,
+/// the value is `{topic, partition, offset}`
+///
+/// ```yaml
+/// brokers: 127.0.0.1:9092
+/// consume:
+///     topic: order-event
+///     group: chord
+///     offset: latest
+///     expect: (eq curr.value.value.id case.id)
+///     timeout: 10
+/// ```
+///
+/// waits for the first message matching `expect`, the value is
+/// `{topic, partition, offset, key, value, headers, timestamp}`, `value` parsed as json when possible.
+/// without `group` the consumer starts from the latest offset of every partition,
+/// with it the committed offset of the group is used, or `offset` when there is none
+#[async_trait]
+impl Action for Kafka {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let args = arg.args();
+        if args["produce"].is_object() {
+            return Ok(Box::new(self.produce(arg).await?));
+        }
+        if args["consume"].is_object() {
+            return Ok(Box::new(self.consume(arg).await?));
+        }
+        Err(err!("101", "missing produce or consume"))
+    }
+}
+
+impl Kafka {
+    async fn produce(&self, arg: &dyn RunArg) -> Result<Value, Error> {
+        let args = arg.args();
+        let produce = &args["produce"];
+        let owned;
+        let producer = match self.producer.as_ref() {
+            Some(p) => p,
+            None => {
+                let brokers = args["brokers"]
+                    .as_str()
+                    .ok_or(err!("100", "missing brokers"))?;
+                let mut config = client_config(brokers, &args["config"]);
+                if let Some(p) = produce["partitioner"].as_str() {
+                    config.set("partitioner", p);
+                }
+                owned = producer_create(&config)?;
+                &owned
+            }
+        };
+
+        let topic = produce["topic"]
+            .as_str()
+            .ok_or(err!("102", "missing topic"))?;
+        let payload = match &produce["value"] {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            v => Some(v.to_string()),
+        };
+        let key = match &produce["key"] {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            v => Some(v.to_string()),
+        };
+
+        let mut record: FutureRecord<String, String> = FutureRecord::to(topic);
+        if let Some(p) = payload.as_ref() {
+            record = record.payload(p);
+        }
+        if let Some(k) = key.as_ref() {
+            record = record.key(k);
+        }
+        if let Some(p) = produce["partition"].as_i64() {
+            record = record.partition(p as i32);
+        }
+        if let Some(headers) = produce["headers"].as_object() {
+            let mut owned_headers = OwnedHeaders::new();
+            for (k, v) in headers {
+                owned_headers = match v {
+                    Value::String(s) => owned_headers.add(k.as_str(), s.as_str()),
+                    _ => owned_headers.add(k.as_str(), v.to_string().as_str()),
+                };
+            }
+            record = record.headers(owned_headers);
+        }
+
+        let (partition, offset) = timeout(
+            arg.timeout(),
+            producer.send(record, Timeout::After(arg.timeout())),
+        )
+        .await
+        .or(Err(err!("104", "timeout")))?
+        .map_err(|(e, _)| err!("104", e.to_string()))?;
+        Ok(json!({
+            "topic": topic,
+            "partition": partition,
+            "offset": offset
+        }))
+    }
+
+    async fn consume(&self, arg: &dyn RunArg) -> Result<Value, Error> {
+        let args = arg.args();
+        let consume = &args["consume"];
+        let duration = consume["timeout"]
+            .as_u64()
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| arg.timeout());
+        let condition = consume["expect"].as_str();
+
+        let pending = match self.consumer.as_ref() {
+            Some(c) => c.clone(),
+            None => {
+                let brokers = args["brokers"]
+                    .as_str()
+                    .ok_or(err!("100", "missing brokers"))?;
+                let topic = consume["topic"]
+                    .as_str()
+                    .ok_or(err!("102", "missing topic"))?;
+                consumer_create(
+                    brokers,
+                    &args["config"],
+                    topic,
+                    consume["group"].as_str(),
+                    consume["offset"].as_str(),
+                )
+                .await?
+            }
+        };
+
+        let deadline = Instant::now() + duration;
+        let mut seen = 0;
+        loop {
+            {
+                let mut p = pending.lock().await;
+                let matched = p.messages.iter().position(|(seq, m)| {
+                    *seq >= seen && condition.into_iter().all(|c| arg.assert(c, m))
+                });
+                if let Some(i) = matched {
+                    return Ok(p.messages.remove(i).map(|(_, m)| m).unwrap_or(Value::Null));
+                }
+                seen = p.seq;
+
+                let consumer = p.consumer.clone();
+                match timeout(Duration::from_millis(200), consumer.recv()).await {
+                    Ok(Ok(m)) => {
+                        let value = message_value(&m);
+                        let seq = p.seq;
+                        p.seq += 1;
+                        p.messages.push_back((seq, value));
+                        if p.messages.len() > PENDING_MAX {
+                            p.messages.pop_front();
+                        }
+                    }
+                    Ok(Err(e)) => return Err(err!("105", e.to_string())),
+                    Err(_) => {}
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(err!(
+                    "106",
+                    format!("expect timeout: {}", condition.unwrap_or("any"))
+                ));
+            }
+        }
+    }
+}
+
+fn client_config(brokers: &str, extra: &Value) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config.set("bootstrap.servers", brokers);
+    if let Some(extra) = extra.as_object() {
+        for (k, v) in extra {
+            match v {
+                Value::String(s) => config.set(k.as_str(), s.as_str()),
+                _ => config.set(k.as_str(), v.to_string().as_str()),
+            };
+        }
+    }
+    config
+}
+
+fn producer_create(
+    config: &ClientConfig,
+) -> Result<FutureProducer<DefaultClientContext, AsyncStdRuntime>, Error> {
+    config
+        .create_with_context(DefaultClientContext)
+        .map_err(|e| err!("103", format!("producer: {}", e)))
+}
+
+async fn consumer_create(
+    brokers: &str,
+    extra: &Value,
+    topic: &str,
+    group: Option<&str>,
+    offset: Option<&str>,
+) -> Result<Arc<Mutex<Pending>>, Error> {
+    let mut config = client_config(brokers, extra);
+    match group {
+        Some(g) => {
+            config
+                .set("group.id", g)
+                .set("auto.offset.reset", offset.unwrap_or("latest"));
+        }
+        None => {
+            // assigned partitions only, nothing to commit
+            config
+                .set("group.id", "chord")
+                .set("enable.auto.commit", "false")
+                .set("enable.auto.offset.store", "false");
+        }
+    }
+    let consumer: StreamConsumer<DefaultConsumerContext, AsyncStdRuntime> = config
+        .create()
+        .map_err(|e| err!("103", format!("consumer: {}", e)))?;
+    let consumer = Arc::new(consumer);
+
+    match group {
+        Some(_) => consumer
+            .subscribe(&[topic])
+            .map_err(|e| err!("105", e.to_string()))?,
+        None => {
+            let c = consumer.clone();
+            let topic = topic.to_owned();
+            // the high watermark of now, so nothing sent after this is missed
+            let (tx, rx) = oneshot::channel();
+            std::thread::spawn(move || {
+                let _ = tx.send(assign_latest(c.as_ref(), topic.as_str()));
+            });
+            rx.await.or(Err(err!("105", "assign canceled")))??;
+        }
+    }
+
+    Ok(Arc::new(Mutex::new(Pending {
+        consumer,
+        messages: VecDeque::new(),
+        seq: 0,
+    })))
+}
+
+/// partitions of the topic at their high watermark, blocking
+fn assign_latest(
+    c: &StreamConsumer<DefaultConsumerContext, AsyncStdRuntime>,
+    topic: &str,
+) -> Result<(), Error> {
+    let wait = Duration::from_secs(10);
+    let metadata = c
+        .fetch_metadata(Some(topic), wait)
+        .map_err(|e| err!("105", e.to_string()))?;
+    let partitions = metadata
+        .topics()
+        .iter()
+        .find(|t| t.name() == topic)
+        .map(|t| t.partitions().iter().map(|p| p.id()).collect::<Vec<i32>>())
+        .unwrap_or_default();
+    if partitions.is_empty() {
+        return Err(err!("105", format!("topic {} has no partition", topic)));
+    }
+    let mut assignment = TopicPartitionList::new();
+    for p in partitions {
+        let (_, high) = c
+            .fetch_watermarks(topic, p, wait)
+            .map_err(|e| err!("105", e.to_string()))?;
+        assignment
+            .add_partition_offset(topic, p, Offset::Offset(high))
+            .map_err(|e| err!("105", e.to_string()))?;
+    }
+    c.assign(&assignment)
+        .map_err(|e| err!("105", e.to_string()))
+}
+
+fn message_value<M: Message>(m: &M) -> Value {
+    let text = |b: &[u8]| {
+        let s = String::from_utf8_lossy(b).to_string();
+        from_str(s.as_str()).unwrap_or(Value::String(s))
+    };
+    let mut headers = Map::new();
+    if let Some(h) = m.headers() {
+        for i in 0..h.count() {
+            if let Some((k, v)) = h.get(i) {
+                headers.insert(
+                    k.to_owned(),
+                    Value::String(String::from_utf8_lossy(v).to_string()),
+                );
+            }
+        }
+    }
+    json!({
+        "topic": m.topic(),
+        "partition": m.partition(),
+        "offset": m.offset(),
+        "key": m.key().map_or(Value::Null, |k| Value::String(String::from_utf8_lossy(k).to_string())),
+        "value": m.payload().map_or(Value::Null, text),
+        "headers": headers,
+        "timestamp": m.timestamp().to_millis()
+    })
+}
+
+/// timers and the consumer wake loop of rdkafka on async-std
+struct AsyncStdRuntime;
+
+impl AsyncRuntime for AsyncStdRuntime {
+    type Delay = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    fn spawn<T>(task: T)
+    where
+        T: Future<Output = ()> + Send + 'static,
+    {
+        spawn(task);
+    }
+
+    fn delay_for(duration: Duration) -> Self::Delay {
+        Box::pin(sleep(duration))
+    }
+}
+
+#[test]
+fn message_value_test() {
+    use rdkafka::message::{OwnedMessage, Timestamp};
+
+    let m = OwnedMessage::new(
+        Some(b"{\"id\":1}".to_vec()),
+        Some(b"k1".to_vec()),
+        "order".to_owned(),
+        Timestamp::CreateTime(1000),
+        2,
+        7,
+        Some(OwnedHeaders::new().add("trace", "t1")),
+    );
+    assert_eq!(
+        json!({
+            "topic": "order",
+            "partition": 2,
+            "offset": 7,
+            "key": "k1",
+            "value": {"id": 1},
+            "headers": {"trace": "t1"},
+            "timestamp": 1000
+        }),
+        message_value(&m)
+    );
+}
@@ -1,83 +1,117 @@
-use rlua::StdLib;
+use std::sync::{Arc, Mutex};
+
+use async_std::task::spawn_blocking;
 
 use chord::action::prelude::*;
-use chord::value::{Map, Number};
+use chord::lua;
+use chord::lua::{rlua, Limit};
 
-pub struct LuaFactory {}
+/// ```yaml
+/// lua:
+///     memory_limit: 1024000
+///     instruction_limit: 100000000
+///     helper_timeout: 5
+/// ```
+///
+/// the limits apply to `assert_lua` and the helpers of `helpers.lua` as well,
+/// the default is 1024000 bytes and 100000000 instructions,
+/// a helper call is stopped after `helper_timeout` seconds, 5 by default
+pub struct LuaFactory {
+    limit: Limit,
+}
 
 impl LuaFactory {
-    pub async fn new(_: Option<Value>) -> Result<LuaFactory, Error> {
-        Ok(LuaFactory {})
+    pub async fn new(config: Option<Value>) -> Result<LuaFactory, Error> {
+        let limit = match config {
+            Some(c) => Limit::default().with(&c),
+            None => Limit::default(),
+        };
+        Ok(LuaFactory { limit })
     }
 }
 
 #[async_trait]
 impl Factory for LuaFactory {
     async fn create(&self, _: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
-        Ok(Box::new(Lua {}))
+        Ok(Box::new(Lua { limit: self.limit }))
     }
 }
 
-struct Lua {}
+struct Lua {
+    limit: Limit,
+}
 
+/// ```yaml
+/// state: case
+/// memory_limit: 1024000
+/// instruction_limit: 100000000
+/// global:
+///     size: 10
+/// code: |
+///     local util = require("util")
+///     count = (count or 0) + 1
+///     return util.page(step.search.value, size, count)
+/// ```
+///
+/// `state: case` or `state: task` keeps globals between runs of the lua steps of a case or a task,
+/// the runs sharing a state take turns, a new state is made for each run without it.
+/// `case`, `step`, `def` and `pre` are the render context, read only.
+/// `require("util")` loads `util.lua` in the task dir, `require("lib.util")` loads `lib/util.lua`.
+/// tables with keys `1..n` are arrays.
+/// `memory_limit` and `instruction_limit` of a step only lower the configured ones
 #[async_trait]
 impl Action for Lua {
     async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
-        let rt = rlua::Lua::new_with(
-            StdLib::BASE | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH,
-        );
-        rt.set_memory_limit(Some(1024000));
-        rt.context(|lua| {
-            let args = arg.args();
-
-            if let Some(globals) = args["global"].as_object() {
-                for (k, v) in globals {
-                    let v = rlua_serde::to_value(lua, v)?;
-                    lua.globals().set(k.as_str(), v)?;
-                }
-            }
+        let args = arg.args();
+        let code = args["code"]
+            .as_str()
+            .ok_or(err!("100", "missing code"))?
+            .to_owned();
+        let limit = self.limit.within(args);
 
-            let code = arg.args()["code"]
-                .as_str()
-                .ok_or(err!("100", "missing code"))?;
+        let session = match args["state"].as_str() {
+            None => None,
+            Some("case") => Some(arg.case_session()),
+            Some("task") => Some(arg.task_session()),
+            Some(s) => return Err(err!("103", format!("unsupported state {}", s))),
+        };
+        let rt = match session {
+            None => Arc::new(Mutex::new(lua::sandbox(arg.dir())?)),
+            Some(session) => session
+                .resource_or_insert_with("lua", &mut || {
+                    Ok(Arc::new(Mutex::new(lua::sandbox(arg.dir())?)))
+                })?
+                .downcast::<Mutex<rlua::Lua>>()
+                .or(Err(err!("103", "resource is not a lua state")))?,
+        };
 
-            self.eval(lua, code.to_string())
+        // the runs sharing a state wait for each other, off the async workers
+        let context = arg.context().clone();
+        let global = args["global"].clone();
+        let timeout = arg.timeout();
+        let value = spawn_blocking(move || {
+            let rt = rt.lock().or(Err(err!("103", "lua state poisoned")))?;
+            lua::limit(&rt, limit, timeout);
+            eval(&rt, &context, &global, code.as_str())
         })
+        .await?;
+        Ok(Box::new(value))
     }
 }
 
-impl Lua {
-    fn eval(&self, lua: rlua::Context, code: String) -> Result<Box<dyn Scope>, Error> {
-        match lua.load(code.as_str()).eval::<rlua::Value>() {
-            Ok(v) => {
-                let v: Value = to_value(&v)?;
-                Ok(Box::new(v))
-            }
-            Err(e) => Err(err!("101", format!("{}", e))),
-        }
-    }
-}
+fn eval(rt: &rlua::Lua, context: &Value, global: &Value, code: &str) -> Result<Value, Error> {
+    rt.context(|ctx| {
+        lua::context_set(ctx, context)?;
 
-fn to_value(lua_value: &rlua::Value) -> Result<Value, Error> {
-    match lua_value {
-        rlua::Value::Nil => Ok(Value::Null),
-        rlua::Value::String(v) => Ok(Value::String(v.to_str()?.to_string())),
-        rlua::Value::Integer(v) => Ok(Value::Number(Number::from(v.clone()))),
-        rlua::Value::Boolean(v) => Ok(Value::Bool(v.clone())),
-
-        rlua::Value::Number(v) => {
-            Ok(Number::from_f64(v.clone()).map_or(Value::Null, |v| Value::Number(v)))
-        }
-        rlua::Value::Table(v) => {
-            let mut map = Map::new();
-            for pair in v.clone().pairs::<String, rlua::Value>() {
-                let (k, v) = pair?;
-                let v = to_value(&v)?;
-                map.insert(k, v);
+        if let Some(globals) = global.as_object() {
+            for (k, v) in globals {
+                ctx.globals().set(k.as_str(), lua::from_value(ctx, v)?)?;
             }
-            Ok(Value::Object(map))
         }
 
-        _ => Err(err!("102", "invalid value")),
-    }
+        match ctx.load(code).eval::<rlua::Value>() {
+            Ok(v) => lua::to_value(&v),
+            Err(e) => Err(err!("101", format!("{}", e))),
+        }
+    })
 }
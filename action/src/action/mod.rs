@@ -8,6 +8,8 @@ mod echo;
 mod log;
 mod sleep;
 
+#[cfg(feature = "act_amqp")]
+mod amqp;
 #[cfg(feature = "act_crypto")]
 mod crypto;
 #[cfg(feature = "act_database")]
@@ -20,18 +22,53 @@ mod download;
 mod dubbo;
 #[cfg(feature = "act_dylib")]
 mod dylib;
+#[cfg(feature = "act_exec")]
+mod exec;
+#[cfg(feature = "act_file")]
+mod file;
 #[cfg(feature = "act_fstore")]
 mod fstore;
+#[cfg(feature = "act_graphql")]
+mod graphql;
+#[cfg(feature = "act_grpc")]
+mod grpc;
+#[cfg(any(
+    feature = "act_restapi",
+    feature = "act_graphql",
+    feature = "act_download",
+    feature = "act_wasm"
+))]
+mod http;
+#[cfg(feature = "act_kafka")]
+mod kafka;
 #[cfg(feature = "act_lua")]
 mod lua;
 #[cfg(feature = "act_mongodb")]
 mod mongodb;
+#[cfg(feature = "act_mqtt")]
+mod mqtt;
+#[cfg(feature = "act_plugin")]
+mod plugin;
 #[cfg(feature = "act_redis")]
 mod redis;
 #[cfg(feature = "act_restapi")]
 mod restapi;
+#[cfg(feature = "act_socket")]
+mod socket;
 #[cfg(feature = "act_url")]
 mod url;
+#[cfg(feature = "act_wasm")]
+mod wasm;
+#[cfg(feature = "act_websocket")]
+mod websocket;
+#[cfg(any(
+    feature = "act_docker",
+    feature = "act_exec",
+    feature = "act_file",
+    feature = "act_fstore",
+    feature = "act_restapi"
+))]
+mod workdir;
 
 pub struct FactoryComposite {
     table: HashMap<String, Box<dyn Factory>>,
@@ -68,6 +105,45 @@ impl FactoryComposite {
             true
         );
 
+        #[cfg(feature = "act_graphql")]
+        register!(
+            table,
+            config_ref,
+            "graphql",
+            graphql::GraphqlFactory::new,
+            true
+        );
+
+        #[cfg(feature = "act_grpc")]
+        register!(table, config_ref, "grpc", grpc::GrpcFactory::new, false);
+
+        #[cfg(feature = "act_websocket")]
+        register!(
+            table,
+            config_ref,
+            "websocket",
+            websocket::WebsocketFactory::new,
+            true
+        );
+
+        #[cfg(feature = "act_socket")]
+        register!(
+            table,
+            config_ref,
+            "socket",
+            socket::SocketFactory::new,
+            true
+        );
+
+        #[cfg(feature = "act_kafka")]
+        register!(table, config_ref, "kafka", kafka::KafkaFactory::new, true);
+
+        #[cfg(feature = "act_amqp")]
+        register!(table, config_ref, "amqp", amqp::AmqpFactory::new, false);
+
+        #[cfg(feature = "act_mqtt")]
+        register!(table, config_ref, "mqtt", mqtt::MqttFactory::new, true);
+
         #[cfg(feature = "act_crypto")]
         register!(
             table,
@@ -110,9 +186,15 @@ impl FactoryComposite {
         #[cfg(feature = "act_docker")]
         register!(table, config_ref, "docker", docker::Docker::new, false);
 
+        #[cfg(feature = "act_exec")]
+        register!(table, config_ref, "exec", exec::ExecFactory::new, false);
+
         #[cfg(feature = "act_lua")]
         register!(table, config_ref, "lua", lua::LuaFactory::new, false);
 
+        #[cfg(feature = "act_wasm")]
+        register!(table, config_ref, "wasm", wasm::WasmFactory::new, false);
+
         #[cfg(feature = "act_download")]
         register!(
             table,
@@ -131,6 +213,19 @@ impl FactoryComposite {
             false
         );
 
+        #[cfg(feature = "act_file")]
+        register!(table, config_ref, "file", file::FileFactory::new, false);
+
+        #[cfg(feature = "act_plugin")]
+        if enable(config_ref, "plugin", false) {
+            for (name, factory) in plugin::load(config_ref)? {
+                if table.contains_key(name.as_str()) {
+                    return Err(err!("003", format!("duplicate action {}", name)));
+                }
+                table.insert(name, Box::new(factory));
+            }
+        }
+
         Ok(FactoryComposite { table })
     }
 }
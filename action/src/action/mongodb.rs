@@ -1,70 +1,295 @@
-use mongodb::bson::{to_document, Document};
-use mongodb::{options::ClientOptions, Client};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{ClientOptions, FindOneOptions, FindOptions, UpdateOptions};
+use mongodb::{Client, Collection};
 
 use chord::action::prelude::*;
 use chord::value::from_str;
 
-pub struct MongodbFactory {}
+pub struct MongodbFactory {
+    clients: Arc<Mutex<HashMap<String, Client>>>,
+}
 
 impl MongodbFactory {
     pub async fn new(_: Option<Value>) -> Result<MongodbFactory, Error> {
-        Ok(MongodbFactory {})
+        Ok(MongodbFactory {
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        })
     }
 }
 
 #[async_trait]
 impl Factory for MongodbFactory {
     async fn create(&self, _: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
-        Ok(Box::new(Mongodb {}))
+        Ok(Box::new(Mongodb {
+            clients: self.clients.clone(),
+        }))
     }
 }
 
-struct Mongodb {}
+/// one client per connection string, shared by every step of the factory
+struct Mongodb {
+    clients: Arc<Mutex<HashMap<String, Client>>>,
+}
+
+impl Mongodb {
+    async fn client(&self, url: &str) -> Result<Client, Error> {
+        if let Some(client) = self.clients.lock().unwrap().get(url) {
+            return Ok(client.clone());
+        }
+        let client = Client::with_options(ClientOptions::parse(url).await?)?;
+        Ok(self
+            .clients
+            .lock()
+            .unwrap()
+            .entry(url.to_owned())
+            .or_insert(client)
+            .clone())
+    }
+}
 
+/// ```yaml
+/// url: mongodb://127.0.0.1:27017
+/// database: test
+/// collection: user
+/// operation: find
+/// filter: { "age": { "$gt": 18 } }
+/// projection: { "name": 1 }
+/// sort: { "_id": -1 }
+/// limit: 10
+/// skip: 0
+/// ```
+///
+/// operations and their args
+///
+/// * `insert_one`: `document`
+/// * `insert_many`: `documents`
+/// * `find`: `filter`, `projection`, `sort`, `limit`, `skip`
+/// * `find_one`: `filter`, `projection`, `sort`, `skip`
+/// * `update_one`, `update_many`: `filter`, `update`, `upsert`
+/// * `delete_one`, `delete_many`: `filter`
+/// * `count`: `filter`
+/// * `aggregate`: `pipeline`
+/// * `create_index`: `keys`, `options`
+/// * `drop_index`: `name`
+/// * `list_indexes`
+///
+/// updates and deletes require `filter`, `filter: {}` matches every document,
+/// documents are extended json, objects or json strings, so `{ "$oid": "..." }` is an ObjectId,
+/// results are relaxed extended json
 #[async_trait]
 impl Action for Mongodb {
     async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
-        run(arg).await
-    }
-}
+        let args = arg.args();
+        let url = args["url"].as_str().ok_or(err!("100", "missing url"))?;
+        let database = args["database"]
+            .as_str()
+            .ok_or(err!("101", "missing database"))?;
+        let collection = args["collection"]
+            .as_str()
+            .ok_or(err!("102", "missing collection"))?;
+        let op = args["operation"]
+            .as_str()
+            .ok_or(err!("103", "missing operation"))?;
 
-async fn run(arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
-    let url = arg.args()["url"]
-        .as_str()
-        .ok_or(err!("100", "missing url"))?;
-    let database = arg.args()["database"]
-        .as_str()
-        .ok_or(err!("101", "missing database"))?;
-    let collection = arg.args()["collection"]
-        .as_str()
-        .ok_or(err!("102", "missing collection"))?;
-    let op = arg.args()["operation"]
-        .as_str()
-        .ok_or(err!("103", "missing operation"))?;
-    let op_arg = arg.args()["arg"]
-        .as_str()
-        .ok_or(err!("104", "missing arg"))?;
-
-    // Parse a connection string into an options struct.
-    let client_options = ClientOptions::parse(url).await?;
-    // Get a handle to the deployment.
-    let client = Client::with_options(client_options)?;
-    let db = client.database(database);
-    let collection = db.collection::<Document>(collection);
-
-    match op {
-        "insert_many" => {
-            let arg_json: Value = from_str(op_arg)?;
-            match arg_json {
-                Value::Array(arr) => {
-                    let doc_vec: Vec<Document> =
-                        arr.iter().map(|v| to_document(v).unwrap()).collect();
-                    collection.insert_many(doc_vec, None).await?;
-                    return Ok(Box::new(Value::Null));
+        let db = self.client(url).await?.database(database);
+        let coll = db.collection::<Document>(collection);
+        let value = match op {
+            "insert_one" => {
+                let document =
+                    document(&args["document"])?.ok_or(err!("104", "missing document"))?;
+                let res = coll.insert_one(document, None).await?;
+                json!({ "inserted_id": relaxed(res.inserted_id) })
+            }
+            "insert_many" => {
+                // `arg` is the former name of `documents`
+                let documents = match &args["documents"] {
+                    Value::Null => &args["arg"],
+                    d => d,
+                };
+                let documents =
+                    documents_create(documents)?.ok_or(err!("104", "missing documents"))?;
+                let res = coll.insert_many(documents, None).await?;
+                let mut ids: Vec<(usize, Bson)> = res.inserted_ids.into_iter().collect();
+                ids.sort_by_key(|(i, _)| *i);
+                let ids = Bson::Array(ids.into_iter().map(|(_, id)| id).collect());
+                json!({ "inserted_ids": relaxed(ids) })
+            }
+            "find" => {
+                let mut options = FindOptions::default();
+                options.projection = document(&args["projection"])?;
+                options.sort = document(&args["sort"])?;
+                options.limit = args["limit"].as_i64();
+                options.skip = args["skip"].as_i64();
+                let cursor = coll.find(document(&args["filter"])?, options).await?;
+                let found: Vec<Document> = cursor.try_collect().await?;
+                relaxed(Bson::Array(found.into_iter().map(Bson::Document).collect()))
+            }
+            "find_one" => {
+                let mut options = FindOneOptions::default();
+                options.projection = document(&args["projection"])?;
+                options.sort = document(&args["sort"])?;
+                options.skip = args["skip"].as_i64();
+                coll.find_one(document(&args["filter"])?, options)
+                    .await?
+                    .map_or(Value::Null, |d| relaxed(Bson::Document(d)))
+            }
+            "update_one" | "update_many" => update(&coll, op, args).await?,
+            "delete_one" | "delete_many" => {
+                let filter = filter(args)?;
+                let res = if op == "delete_one" {
+                    coll.delete_one(filter, None).await?
+                } else {
+                    coll.delete_many(filter, None).await?
+                };
+                json!({ "deleted_count": res.deleted_count })
+            }
+            "count" => {
+                let count = coll
+                    .count_documents(document(&args["filter"])?, None)
+                    .await?;
+                json!(count)
+            }
+            "aggregate" => {
+                let pipeline =
+                    documents_create(&args["pipeline"])?.ok_or(err!("104", "missing pipeline"))?;
+                let cursor = coll.aggregate(pipeline, None).await?;
+                let found: Vec<Document> = cursor.try_collect().await?;
+                relaxed(Bson::Array(found.into_iter().map(Bson::Document).collect()))
+            }
+            "create_index" => {
+                let keys = document(&args["keys"])?.ok_or(err!("104", "missing keys"))?;
+                let mut index = document(&args["options"])?.unwrap_or_default();
+                if !index.contains_key("name") {
+                    let name = keys
+                        .iter()
+                        .map(|(k, v)| format!("{}_{}", k, relaxed(v.clone())))
+                        .collect::<Vec<String>>()
+                        .join("_");
+                    index.insert("name", name.replace('"', ""));
                 }
-                _ => Err(err!("105", "illegal arg")),
+                index.insert("key", keys);
+                let res = db
+                    .run_command(
+                        doc! { "createIndexes": collection, "indexes": [index] },
+                        None,
+                    )
+                    .await?;
+                relaxed(Bson::Document(res))
+            }
+            "drop_index" => {
+                let name = args["name"].as_str().ok_or(err!("104", "missing name"))?;
+                let res = db
+                    .run_command(doc! { "dropIndexes": collection, "index": name }, None)
+                    .await?;
+                relaxed(Bson::Document(res))
+            }
+            "list_indexes" => {
+                let res = db
+                    .run_command(doc! { "listIndexes": collection }, None)
+                    .await?;
+                let res = relaxed(Bson::Document(res));
+                res["cursor"]["firstBatch"].clone()
             }
+            _ => return Err(err!("106", "illegal operation")),
+        };
+        Ok(Box::new(value))
+    }
+}
+
+async fn update(coll: &Collection<Document>, op: &str, args: &Value) -> Result<Value, Error> {
+    let filter = filter(args)?;
+    let mut options = UpdateOptions::default();
+    options.upsert = args["upsert"].as_bool();
+    let res = match (&args["update"], op) {
+        // an aggregation pipeline
+        (Value::Array(_), "update_one") => {
+            let pipeline = documents_create(&args["update"])?.unwrap_or_default();
+            coll.update_one(filter, pipeline, options).await?
+        }
+        (Value::Array(_), _) => {
+            let pipeline = documents_create(&args["update"])?.unwrap_or_default();
+            coll.update_many(filter, pipeline, options).await?
+        }
+        (u, "update_one") => {
+            let update = document(u)?.ok_or(err!("104", "missing update"))?;
+            coll.update_one(filter, update, options).await?
         }
-        _ => Err(err!("106", "illegal operation")),
+        (u, _) => {
+            let update = document(u)?.ok_or(err!("104", "missing update"))?;
+            coll.update_many(filter, update, options).await?
+        }
+    };
+    Ok(json!({
+        "matched_count": res.matched_count,
+        "modified_count": res.modified_count,
+        "upserted_id": res.upserted_id.map_or(Value::Null, relaxed)
+    }))
+}
+
+/// required, so a missing filter never matches every document by accident
+fn filter(args: &Value) -> Result<Document, Error> {
+    document(&args["filter"])?.ok_or(err!("104", "missing filter"))
+}
+
+/// an object, or a json string of one, read as extended json
+fn document(value: &Value) -> Result<Option<Document>, Error> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => document(&from_str(s.as_str())?),
+        Value::Object(map) => Document::try_from(map.clone())
+            .map(Some)
+            .map_err(|e| err!("105", format!("illegal document: {}", e))),
+        _ => Err(err!("105", "illegal document")),
     }
 }
+
+fn documents_create(value: &Value) -> Result<Option<Vec<Document>>, Error> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => documents_create(&from_str(s.as_str())?),
+        Value::Array(arr) => arr
+            .iter()
+            .map(|v| document(v)?.ok_or(err!("105", "illegal document")))
+            .collect::<Result<Vec<Document>, Error>>()
+            .map(Some),
+        _ => Err(err!("105", "illegal arg")),
+    }
+}
+
+fn relaxed(bson: Bson) -> Value {
+    bson.into_relaxed_extjson()
+}
+
+#[test]
+fn document_test() {
+    let doc = document(&json!({"_id": {"$oid": "5f3f2e1d4c3b2a1908070605"}, "n": 1}))
+        .unwrap()
+        .unwrap();
+    assert!(matches!(doc.get("_id"), Some(Bson::ObjectId(_))));
+    assert_eq!(
+        json!({"_id": {"$oid": "5f3f2e1d4c3b2a1908070605"}, "n": 1}),
+        relaxed(Bson::Document(doc))
+    );
+
+    let docs = documents_create(&json!("[{\"a\": 1}, {\"b\": \"x\"}]"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(2, docs.len());
+
+    assert_eq!("105", document(&json!([1])).unwrap_err().code());
+}
+
+#[test]
+fn filter_test() {
+    assert_eq!("104", filter(&json!({})).unwrap_err().code());
+    assert!(filter(&json!({"filter": {}})).unwrap().is_empty());
+    assert_eq!(
+        Some(&Bson::Int32(1)),
+        filter(&json!({"filter": "{\"a\": 1}"})).unwrap().get("a")
+    );
+}
@@ -0,0 +1,355 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_std::future::timeout;
+use async_std::task::spawn_blocking;
+use libloading::Library;
+use log::{info, trace, warn};
+
+use chord::action::prelude::*;
+use chord_sdk::abi;
+
+/// ```yaml
+/// plugin:
+///     enable: true
+///     dir: /data/chord/plugin
+/// hello:
+///     greeting: hi
+/// ```
+///
+/// every library in `dir` exporting `chord_plugin` of the `chord-sdk` abi is an action named by the plugin,
+/// configured like the builtin actions and enabled unless `enable: false`, other libraries are skipped.
+/// a run calls the plugin on a blocking thread and gives up at the step timeout (104),
+/// the plugin keeps running until it returns, but its `assert` and session callbacks fail from then on
+pub fn load(config: Option<&Value>) -> Result<Vec<(String, PluginFactory)>, Error> {
+    let dir = config
+        .and_then(|c| c["plugin"]["dir"].as_str())
+        .ok_or(err!("100", "missing plugin.dir"))?;
+
+    let mut paths = Vec::new();
+    let entries = std::fs::read_dir(dir).map_err(|e| cause!("100", dir, e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| cause!("100", dir, e))?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some(std::env::consts::DLL_EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut factories = Vec::new();
+    for path in paths {
+        let plugin = match Plugin::open(path.as_path())? {
+            Some(p) => p,
+            None => {
+                warn!("plugin skip {:?}, no chord_plugin", path);
+                continue;
+            }
+        };
+        let name = plugin.name.clone();
+        let conf = config.map(|c| c[name.as_str()].clone());
+        if let Some(false) = conf.as_ref().and_then(|c| c["enable"].as_bool()) {
+            trace!("plugin {} disabled", name);
+            continue;
+        }
+        info!("plugin {} load {:?}", name, path);
+        let plugin = plugin.create(conf)?;
+        factories.push((
+            name,
+            PluginFactory {
+                plugin: Arc::new(plugin),
+            },
+        ));
+    }
+    Ok(factories)
+}
+
+struct Plugin {
+    name: String,
+    decl: *const abi::Plugin,
+    factory: *mut c_void,
+    // dropped last, after the factory and the actions
+    _lib: Library,
+}
+
+// the factory of a plugin is a `Factory`, `Sync + Send`
+unsafe impl Send for Plugin {}
+unsafe impl Sync for Plugin {}
+
+impl Plugin {
+    /// `None` for a library without the symbol, not a plugin
+    fn open(path: &Path) -> Result<Option<Plugin>, Error> {
+        let lib = Library::new(path)
+            .map_err(|e| err!("101", format!("plugin {:?} load fail: {}", path, e)))?;
+        let decl = unsafe {
+            let declare =
+                match lib.get::<unsafe extern "C" fn() -> *const abi::Plugin>(abi::PLUGIN_SYMBOL) {
+                    Ok(declare) => declare,
+                    Err(_) => return Ok(None),
+                };
+            declare()
+        };
+        if decl.is_null() {
+            return Err(err!("101", format!("plugin {:?} load fail: null", path)));
+        }
+
+        let version = unsafe { (*decl).abi_version };
+        if version != abi::ABI_VERSION {
+            return Err(err!(
+                "102",
+                format!(
+                    "plugin {:?} abi version {}, expect {}",
+                    path,
+                    version,
+                    abi::ABI_VERSION
+                )
+            ));
+        }
+        let name = unsafe { abi::str_from((*decl).name)? }.to_owned();
+        Ok(Some(Plugin {
+            name,
+            decl,
+            factory: std::ptr::null_mut(),
+            _lib: lib,
+        }))
+    }
+
+    fn decl(&self) -> &abi::Plugin {
+        unsafe { &*self.decl }
+    }
+
+    fn create(mut self, config: Option<Value>) -> Result<Plugin, Error> {
+        let config = cstring(config.unwrap_or(Value::Null).to_string())?;
+        let mut factory = std::ptr::null_mut();
+        let e = unsafe { (self.decl().factory_create)(config.as_ptr(), &mut factory) };
+        self.error(e)?;
+        self.factory = factory;
+        Ok(self)
+    }
+
+    /// takes a string of the plugin
+    fn take(&self, s: *mut c_char) -> Result<String, Error> {
+        let owned = unsafe { abi::str_from(s) }.map(str::to_owned);
+        unsafe { (self.decl().str_free)(s) };
+        owned
+    }
+
+    fn error(&self, e: *mut c_char) -> Result<(), Error> {
+        if e.is_null() {
+            return Ok(());
+        }
+        let e = self.take(e)?;
+        let e: Value = from_str(e.as_str()).or(Err(err!("103", "invalid error")))?;
+        Err(abi::error_from_value(&e))
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if !self.factory.is_null() {
+            unsafe { (self.decl().factory_destroy)(self.factory) };
+        }
+    }
+}
+
+pub struct PluginFactory {
+    plugin: Arc<Plugin>,
+}
+
+#[async_trait]
+impl Factory for PluginFactory {
+    async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let id = arg.id();
+        let raw = cstring(
+            json!({
+                "id": id.to_string(),
+                "step": id.step(),
+                "task": {
+                    "task": id.task_id().task(),
+                    "exec_id": id.task_id().exec_id()
+                },
+                "action": arg.action(),
+                "args": arg.args(),
+                "dir": arg.dir().to_str()
+            })
+            .to_string(),
+        )?;
+        let host: &dyn CreateArg = arg;
+        let abi_arg = abi::CreateArg {
+            arg: raw.as_ptr(),
+            host: &host as *const &dyn CreateArg as *const c_void,
+            render_str: host_render_str,
+            is_shared: host_is_shared,
+            str_free: abi::str_free,
+        };
+
+        let plugin = self.plugin.as_ref();
+        let mut action = std::ptr::null_mut();
+        let e = unsafe { (plugin.decl().action_create)(plugin.factory, &abi_arg, &mut action) };
+        plugin.error(e)?;
+        Ok(Box::new(PluginAction {
+            action: Arc::new(ActionHandle {
+                plugin: self.plugin.clone(),
+                action,
+            }),
+        }))
+    }
+}
+
+struct PluginAction {
+    action: Arc<ActionHandle>,
+}
+
+/// freed once the action and every run still going on are dropped
+struct ActionHandle {
+    plugin: Arc<Plugin>,
+    action: *mut c_void,
+}
+
+// the action of a plugin is an `Action`, `Sync + Send`
+unsafe impl Send for ActionHandle {}
+unsafe impl Sync for ActionHandle {}
+
+impl Drop for ActionHandle {
+    fn drop(&mut self) {
+        unsafe { (self.plugin.decl().action_free)(self.action) };
+    }
+}
+
+/// the run arg of a call, taken away at the timeout while no callback is using it
+struct Host(Mutex<Option<&'static dyn RunArg>>);
+
+impl Host {
+    fn with<T>(&self, f: impl FnOnce(&dyn RunArg) -> T) -> Option<T> {
+        let arg = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        arg.map(f)
+    }
+
+    fn clear(&self) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+#[async_trait]
+impl Action for PluginAction {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let id = arg.id();
+        let case_id = id.case_id();
+        let raw = cstring(
+            json!({
+                "id": id.to_string(),
+                "step": id.step(),
+                "case": {
+                    "case": case_id.case(),
+                    "exec_id": case_id.exec_id()
+                },
+                "task": {
+                    "task": case_id.task_id().task(),
+                    "exec_id": case_id.task_id().exec_id()
+                },
+                "args": arg.args(),
+                "context": arg.context(),
+                "dir": arg.dir().to_str(),
+                "timeout": arg.timeout().as_millis() as u64
+            })
+            .to_string(),
+        )?;
+        // the borrow never outlives this run, `clear` ends it before returning
+        let host = Arc::new(Host(Mutex::new(Some(unsafe {
+            std::mem::transmute::<&dyn RunArg, &'static dyn RunArg>(arg)
+        }))));
+        let action = self.action.clone();
+        let call_host = host.clone();
+        let call = spawn_blocking(move || {
+            let abi_arg = abi::RunArg {
+                arg: raw.as_ptr(),
+                host: Arc::as_ptr(&call_host) as *const c_void,
+                assert: host_assert,
+                session_get: host_session_get,
+                session_set: host_session_set,
+                str_free: abi::str_free,
+            };
+            let plugin = action.plugin.as_ref();
+            let res = unsafe { (plugin.decl().action_run)(action.action, &abi_arg) };
+            plugin.take(res)
+        });
+        let res = timeout(arg.timeout(), call).await;
+        host.clear();
+        let res = res.or(Err(err!("104", "timeout")))??;
+        let value = abi::result_from_str(res.as_str())?;
+        Ok(Box::new(value))
+    }
+}
+
+fn cstring(s: String) -> Result<CString, Error> {
+    CString::new(s).or(Err(err!("103", "nul in string")))
+}
+
+unsafe fn create_arg<'a>(host: *const c_void) -> &'a dyn CreateArg {
+    *(host as *const &dyn CreateArg)
+}
+
+unsafe fn run_arg<'a>(host: *const c_void) -> &'a Host {
+    &*(host as *const Host)
+}
+
+unsafe extern "C" fn host_render_str(host: *const c_void, text: *const c_char) -> *mut c_char {
+    let rendered = abi::str_from(text).and_then(|text| create_arg(host).render_str(text));
+    abi::result_to_str(rendered.map(Value::String))
+}
+
+unsafe extern "C" fn host_is_shared(host: *const c_void, text: *const c_char) -> bool {
+    match abi::str_from(text) {
+        Ok(text) => create_arg(host).is_shared(text),
+        Err(_) => false,
+    }
+}
+
+unsafe extern "C" fn host_assert(
+    host: *const c_void,
+    condition: *const c_char,
+    value: *const c_char,
+) -> bool {
+    let value = abi::str_from(value).and_then(|v| Ok(from_str::<Value>(v)?));
+    match (abi::str_from(condition), value) {
+        (Ok(condition), Ok(value)) => run_arg(host)
+            .with(|arg| arg.assert(condition, &value))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn host_session(arg: &dyn RunArg, scope: u32) -> &dyn Session {
+    if scope == abi::SESSION_TASK {
+        arg.task_session()
+    } else {
+        arg.case_session()
+    }
+}
+
+unsafe extern "C" fn host_session_get(
+    host: *const c_void,
+    scope: u32,
+    key: *const c_char,
+) -> *mut c_char {
+    let value = match abi::str_from(key) {
+        Ok(key) => run_arg(host)
+            .with(|arg| host_session(arg, scope).get(key))
+            .unwrap_or(Value::Null),
+        Err(_) => Value::Null,
+    };
+    abi::str_into(value.to_string())
+}
+
+unsafe extern "C" fn host_session_set(
+    host: *const c_void,
+    scope: u32,
+    key: *const c_char,
+    value: *const c_char,
+) {
+    let value = abi::str_from(value).and_then(|v| Ok(from_str::<Value>(v)?));
+    if let (Ok(key), Ok(value)) = (abi::str_from(key), value) {
+        run_arg(host).with(|arg| host_session(arg, scope).set(key, value));
+    }
+}
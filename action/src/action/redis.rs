@@ -1,4 +1,10 @@
-use redis::{Client, Value as RedisValue};
+use std::time::Duration;
+
+use async_std::sync::Mutex;
+use redis::aio::MultiplexedConnection;
+use redis::cluster::ClusterClient;
+use redis::cluster_async::ClusterConnection;
+use redis::{Client, Cmd, Pipeline, RedisResult, Value as RedisValue};
 
 use chord::action::prelude::*;
 use chord::value::{from_str, Number};
@@ -20,69 +26,250 @@ impl Factory for RedisFactory {
             .ok_or(err!("100", "missing url"))??;
 
         if !arg.is_shared(url.as_str()) {
-            return Ok(Box::new(Redis { client: None }));
+            return Ok(Box::new(Redis {
+                url: None,
+                conn: Mutex::new(None),
+            }));
         }
 
-        let client = redis::Client::open(url)?;
-
         Ok(Box::new(Redis {
-            client: Some(client),
+            url: Some(url),
+            conn: Mutex::new(None),
         }))
     }
 }
 
+/// a shared url keeps one multiplexed connection, opened on first use and reopened after io errors
 struct Redis {
-    client: Option<Client>,
+    url: Option<String>,
+    conn: Mutex<Option<Conn>>,
+}
+
+#[derive(Clone)]
+enum Conn {
+    Single(MultiplexedConnection),
+    Cluster(ClusterConnection),
 }
 
 #[async_trait]
 impl Action for Redis {
     async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
-        return match self.client.as_ref() {
-            Some(r) => run0(arg, r).await,
-            None => {
-                let url = arg.args()["url"]
-                    .as_str()
-                    .ok_or(err!("101", "missing url"))?;
-
-                let client = redis::Client::open(url)?;
-                run0(arg, &client).await
+        let url = match self.url.as_ref() {
+            Some(url) => url.as_str(),
+            None => arg.args()["url"]
+                .as_str()
+                .ok_or(err!("101", "missing url"))?,
+        };
+
+        let request = request_create(arg.args())?;
+        let mut conn = {
+            let mut cached = self.conn.lock().await;
+            match cached.as_ref() {
+                Some(c) => c.clone(),
+                None => {
+                    let c = connect(url, arg.timeout()).await?;
+                    if self.url.is_some() {
+                        *cached = Some(c.clone());
+                    }
+                    c
+                }
             }
         };
+
+        let value = match conn.query(&request).await {
+            Ok(v) => v,
+            Err(e) => {
+                if e.is_io_error() || e.is_connection_dropped() {
+                    self.conn.lock().await.take();
+                }
+                return Err(e.into());
+            }
+        };
+        Ok(Box::new(value_create(value)))
     }
 }
 
-async fn run0(arg: &dyn RunArg, client: &Client) -> Result<Box<dyn Scope>, Error> {
-    let cmd = arg.args()["cmd"]
-        .as_str()
-        .ok_or(err!("102", "missing cmd"))?;
+/// ```yaml
+/// url: redis://127.0.0.1:6379/0
+/// url: redis-cluster://:password@127.0.0.1:7000,127.0.0.1:7001
+/// url: redis-sentinel://:password@127.0.0.1:26379,127.0.0.1:26380/0#mymaster
+/// ```
+///
+/// the password of cluster and sentinel urls is that of the data nodes,
+/// the timeout of the step opening the connection applies to every later reply on it
+async fn connect(url: &str, timeout: Duration) -> Result<Conn, Error> {
+    if let Some(rest) = url.strip_prefix("redis-cluster://") {
+        let (auth, hosts) = auth_split(rest);
+        let nodes: Vec<String> = hosts
+            .split(',')
+            .map(|h| format!("redis://{}{}", auth, h.trim()))
+            .collect();
+        let conn = ClusterClient::builder(nodes)
+            .connection_timeout(timeout)
+            .response_timeout(timeout)
+            .build()?
+            .get_async_connection()
+            .await?;
+        return Ok(Conn::Cluster(conn));
+    }
+
+    if let Some(rest) = url.strip_prefix("redis-sentinel://") {
+        let (rest, master) = rest.split_once('#').ok_or(err!(
+            "103",
+            "missing master name, redis-sentinel://host:port#master"
+        ))?;
+        let (auth, rest) = auth_split(rest);
+        let (hosts, db) = rest.split_once('/').unwrap_or((rest, "0"));
+        db.parse::<i64>()
+            .or(Err(err!("103", format!("invalid db {}", db))))?;
+
+        for host in hosts.split(',') {
+            let sentinel = format!("redis://{}", host.trim());
+            let addr: RedisResult<Option<(String, u16)>> = async {
+                let mut conn = Client::open(sentinel.as_str())?
+                    .get_multiplexed_async_connection_with_timeouts(timeout, timeout)
+                    .await?;
+                redis::cmd("SENTINEL")
+                    .arg("get-master-addr-by-name")
+                    .arg(master)
+                    .query_async(&mut conn)
+                    .await
+            }
+            .await;
+            if let Ok(Some((ip, port))) = addr {
+                let url = format!("redis://{}{}:{}/{}", auth, ip, port, db);
+                return single_connect(url.as_str(), timeout).await;
+            }
+        }
+        return Err(err!("105", format!("no sentinel knows master {}", master)));
+    }
+
+    single_connect(url, timeout).await
+}
 
-    let mut con = client.get_async_connection().await?;
+async fn single_connect(url: &str, timeout: Duration) -> Result<Conn, Error> {
+    let conn = Client::open(url)?
+        .get_multiplexed_async_connection_with_timeouts(timeout, timeout)
+        .await?;
+    Ok(Conn::Single(conn))
+}
 
+/// `user:password@` prefix of the hosts, kept with its `@`
+fn auth_split(rest: &str) -> (&str, &str) {
+    match rest.rfind('@') {
+        Some(i) => (&rest[..i + 1], &rest[i + 1..]),
+        None => ("", rest),
+    }
+}
+
+/// ```yaml
+/// cmd: HGETALL
+/// args: [ "user:{{case.id}}" ]
+/// ```
+///
+/// or a list of commands sent at once, inside MULTI/EXEC with `atomic: true`
+///
+/// ```yaml
+/// pipeline:
+///     - [ SET, "user:{{case.id}}", "{{case.name}}" ]
+///     - cmd: GET
+///       args: [ "user:{{case.id}}" ]
+/// atomic: true
+/// ```
+fn request_create(args: &Value) -> Result<Request, Error> {
+    if let Some(items) = args["pipeline"].as_array() {
+        let mut pipe = redis::pipe();
+        if args["atomic"].as_bool().unwrap_or(false) {
+            pipe.atomic();
+        }
+        for item in items {
+            let (cmd, cmd_args) = match item {
+                Value::Array(a) if !a.is_empty() => (&a[0], &a[1..]),
+                _ => (
+                    &item["cmd"],
+                    item["args"].as_array().map_or(&[][..], |a| &a[..]),
+                ),
+            };
+            let cmd = cmd.as_str().ok_or(err!(
+                "104",
+                "pipeline item must be [cmd, args...] or {cmd, args}"
+            ))?;
+            pipe.add_command(command_create(cmd, cmd_args));
+        }
+        return Ok(Request::Pipeline(pipe));
+    }
+
+    let cmd = args["cmd"].as_str().ok_or(err!("102", "missing cmd"))?;
+    let cmd_args = args["args"].as_array().map_or(&[][..], |a| &a[..]);
+    Ok(Request::Cmd(command_create(cmd, cmd_args)))
+}
+
+/// strings go raw, anything else as json text
+fn command_create(cmd: &str, args: &[Value]) -> Cmd {
     let mut command = redis::cmd(cmd);
-    let args_opt = &arg.args()["args"];
+    for a in args {
+        match a {
+            Value::String(s) => command.arg(s.as_str()),
+            _ => command.arg(a.to_string()),
+        };
+    }
+    command
+}
+
+enum Request {
+    Cmd(Cmd),
+    Pipeline(Pipeline),
+}
 
-    if let Some(arg_vec) = args_opt.as_array() {
-        for a in arg_vec {
-            command.arg(a.to_string());
+impl Conn {
+    async fn query(&mut self, request: &Request) -> RedisResult<RedisValue> {
+        match (self, request) {
+            (Conn::Single(c), Request::Cmd(cmd)) => cmd.query_async(c).await,
+            (Conn::Cluster(c), Request::Cmd(cmd)) => cmd.query_async(c).await,
+            (Conn::Single(c), Request::Pipeline(pipe)) => pipe.query_async(c).await,
+            (Conn::Cluster(c), Request::Pipeline(pipe)) => pipe.query_async(c).await,
         }
     }
+}
 
-    let redis_value: RedisValue = command.query_async(&mut con).await?;
-    let result = match &redis_value {
+/// bulk replies become arrays, nested ones included, data is parsed as json when possible
+fn value_create(value: RedisValue) -> Value {
+    match value {
         RedisValue::Nil => Value::Null,
-        RedisValue::Int(i) => Value::Number(Number::from(i.clone())),
+        RedisValue::Int(i) => Value::Number(Number::from(i)),
         RedisValue::Data(data) => {
-            let data = String::from_utf8_lossy(data);
-            let dv = from_str(data.as_ref());
-            match dv {
+            let data = String::from_utf8_lossy(&data);
+            match from_str(data.as_ref()) {
                 Ok(v) => v,
                 Err(_) => Value::String(data.to_string()),
             }
         }
-        RedisValue::Status(status) => Value::String(status.clone()),
+        RedisValue::Bulk(items) => Value::Array(items.into_iter().map(value_create).collect()),
+        RedisValue::Status(status) => Value::String(status),
         RedisValue::Okay => Value::String("OK".to_string()),
-        _ => Value::Array(vec![]),
-    };
-    return Ok(Box::new(result));
+    }
+}
+
+#[test]
+fn value_create_test() {
+    let value = value_create(RedisValue::Bulk(vec![
+        RedisValue::Nil,
+        RedisValue::Int(1),
+        RedisValue::Data(b"{\"a\":1}".to_vec()),
+        RedisValue::Data(b"foo".to_vec()),
+        RedisValue::Bulk(vec![
+            RedisValue::Data(b"bar".to_vec()),
+            RedisValue::Bulk(vec![RedisValue::Int(2)]),
+        ]),
+    ]));
+    assert_eq!(json!([null, 1, {"a": 1}, "foo", ["bar", [2]]]), value);
+
+    let value = value_create(RedisValue::Data(vec![b'a', 0xff, b'b']));
+    assert_eq!(json!("a\u{fffd}b"), value);
+
+    assert_eq!(
+        json!("QUEUED"),
+        value_create(RedisValue::Status("QUEUED".into()))
+    );
+    assert_eq!(json!("OK"), value_create(RedisValue::Okay));
 }
@@ -1,43 +1,68 @@
 use std::borrow::Borrow;
 use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
 
-use surf::http::headers::{HeaderName, HeaderValue};
-use surf::http::Method;
-use surf::{Body, RequestBuilder, Response, Url};
+use async_std::path::PathBuf;
+use isahc::Metrics;
+use surf::http::{Method, Mime};
+use surf::{Body, Client, RequestBuilder, Response, Url};
 
 use chord::action::prelude::*;
-use chord::value::{Map, Number};
+use chord::value::{from_slice, Map, Number};
 
-pub struct RestapiFactory {}
+use crate::action::http;
+use crate::action::http::client::ClientPool;
+use crate::action::http::cookie;
+use crate::action::workdir::check_path;
+
+pub struct RestapiFactory {
+    pool: ClientPool,
+    workdir: Option<PathBuf>,
+}
 
 impl RestapiFactory {
-    pub async fn new(_: Option<Value>) -> Result<RestapiFactory, Error> {
-        Ok(RestapiFactory {})
+    pub async fn new(config: Option<Value>) -> Result<RestapiFactory, Error> {
+        let pool = ClientPool::new(config.as_ref())?;
+        let workdir = match config.as_ref().and_then(|c| c["workdir"].as_str()) {
+            Some(w) => Some(PathBuf::from_str(w)?),
+            None => None,
+        };
+        Ok(RestapiFactory { pool, workdir })
     }
 }
 
 #[async_trait]
 impl Factory for RestapiFactory {
-    async fn create(&self, _: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
-        Ok(Box::new(Restapi {}))
+    async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let client = match arg.args()["client"].as_str() {
+            Some(name) => self.pool.get(Some(arg.render_str(name)?.as_str()))?,
+            None => self.pool.get(None)?,
+        };
+        Ok(Box::new(Restapi {
+            client,
+            workdir: self.workdir.clone(),
+        }))
     }
 }
 
-struct Restapi {}
+struct Restapi {
+    client: Client,
+    workdir: Option<PathBuf>,
+}
 
 #[async_trait]
 impl Action for Restapi {
     async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
-        run(arg).await
+        run(self, arg).await
     }
 }
 
-async fn run(arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
-    let value = run0(arg).await.map_err(|e| e.0)?;
+async fn run(restapi: &Restapi, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+    let value = run0(restapi, arg).await.map_err(|e| e.0)?;
     Ok(Box::new(value))
 }
 
-async fn run0(arg: &dyn RunArg) -> std::result::Result<Value, RestapiError> {
+async fn run0(restapi: &Restapi, arg: &dyn RunArg) -> std::result::Result<Value, RestapiError> {
     let args = arg.args();
 
     let url = args["url"].as_str().ok_or(err!("100", "missing url"))?;
@@ -48,40 +73,31 @@ async fn run0(arg: &dyn RunArg) -> std::result::Result<Value, RestapiError> {
         .ok_or(err!("102", "missing method"))?;
     let method = Method::from_str(method).or(Err(err!("103", "invalid method")))?;
 
-    let mut rb = RequestBuilder::new(method, url);
-    rb = rb.header(
-        HeaderName::from_str("Content-Type").unwrap(),
-        HeaderValue::from_str("application/json")?,
-    );
-
-    if let Some(header) = args["header"].as_object() {
-        for (k, v) in header.iter() {
-            let hn =
-                HeaderName::from_string(k.clone()).or(Err(err!("104", "invalid header name")))?;
-            let hvs: Vec<HeaderValue> = match v {
-                Value::String(v) => {
-                    vec![HeaderValue::from_str(v).or(Err(err!("105", "invalid header value")))?]
-                }
-                Value::Array(vs) => {
-                    let mut vec = vec![];
-                    for v in vs {
-                        let v = HeaderValue::from_str(v.to_string().as_str())?;
-                        vec.push(v)
-                    }
-                    vec
-                }
-                _ => Err(err!("106", "invalid header value"))?,
-            };
-            rb = rb.header(hn, hvs.as_slice());
-        }
-    }
+    let mut rb = RequestBuilder::new(method, url.clone());
+
+    rb = http::header_apply(rb, &args["header"])?;
 
     let body = args["body"].borrow();
     if !body.is_null() {
-        rb = rb.body(Body::from(body.clone()));
+        let body_type = args["body_type"].as_str().unwrap_or("json");
+        let task_id = arg.id().case_id().task_id().to_string();
+        let workdir = restapi.workdir.as_ref();
+        rb = rb.body(body_create(workdir, task_id.as_str(), body_type, body).await?);
+    }
+
+    let session = cookie::session(arg)?;
+    let mut req = rb.build();
+    cookie::apply(session, &mut req, &url);
+
+    let req_size = req.len().unwrap_or(0);
+    let start = Instant::now();
+    let mut res: Response = restapi.client.send(req).await?;
+    let header_elapsed = start.elapsed();
+
+    if let Some(s) = session {
+        cookie::store(s, &url, &res);
     }
 
-    let mut res: Response = rb.send().await?;
     let mut res_data = Map::new();
     res_data.insert(
         String::from("status"),
@@ -97,11 +113,237 @@ async fn run0(arg: &dyn RunArg) -> std::result::Result<Value, RestapiError> {
     }
     res_data.insert(String::from("header"), Value::Object(header_data));
 
-    let body: Value = res.body_json().await?;
+    let bytes = res.body_bytes().await?;
+    let total_elapsed = start.elapsed();
+
+    res_data.insert(
+        String::from("size"),
+        json!({
+            "request": req_size,
+            "response": bytes.len()
+        }),
+    );
+    res_data.insert(
+        String::from("time"),
+        time_create(res.ext::<Metrics>(), header_elapsed, total_elapsed),
+    );
+
+    let (body, body_type) = body_decode(res.content_type(), bytes);
     res_data.insert(String::from("body"), body);
+    res_data.insert(
+        String::from("body_type"),
+        Value::String(body_type.to_owned()),
+    );
     return Ok(Value::Object(res_data));
 }
 
+/// `form-urlencoded`, or its alias `form`, takes an object
+async fn body_create(
+    workdir: Option<&PathBuf>,
+    task_id: &str,
+    body_type: &str,
+    body: &Value,
+) -> Result<Body, RestapiError> {
+    match body_type {
+        "json" => Ok(Body::from(body.clone())),
+        "text" => {
+            let text = match body {
+                Value::String(s) => s.clone(),
+                _ => body.to_string(),
+            };
+            Ok(Body::from_string(text))
+        }
+        "form-urlencoded" | "form" => {
+            if !body.is_object() {
+                return Err(err!("110", "form body must be object"))?;
+            }
+            Ok(Body::from_form(body).or(Err(err!("110", "invalid form body")))?)
+        }
+        "binary" => {
+            let encoded = body
+                .as_str()
+                .ok_or(err!("110", "binary body must be base64 string"))?;
+            let bytes =
+                base64::decode(encoded).or(Err(err!("110", "binary body must be base64")))?;
+            Ok(Body::from_bytes(bytes))
+        }
+        "multipart" => {
+            let fields = body
+                .as_object()
+                .ok_or(err!("110", "multipart body must be object"))?;
+            multipart_create(workdir, task_id, fields).await
+        }
+        _ => Err(err!("109", format!("unsupported body_type {}", body_type)))?,
+    }
+}
+
+/// multipart/form-data, a field is either a plain value or a file in the workdir
+///
+/// ```yaml
+/// file:
+///     path: [ "{{step.step1.value.path.0}}", "{{step.step1.value.path.1}}" ]
+///     filename: a.txt
+///     content_type: text/plain
+/// ```
+async fn multipart_create(
+    workdir: Option<&PathBuf>,
+    task_id: &str,
+    fields: &Map,
+) -> Result<Body, RestapiError> {
+    let boundary = format!(
+        "----chord{}",
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+
+    let mut data: Vec<u8> = vec![];
+    for (name, field) in fields {
+        data.extend(format!("--{}\r\n", boundary).as_bytes());
+        if field["path"].is_array() {
+            let path = workdir_file(workdir, task_id, &field["path"])?;
+            let filename = match field["filename"].as_str() {
+                Some(f) => f.to_owned(),
+                None => path
+                    .file_name()
+                    .map(|f| f.to_string_lossy().to_string())
+                    .unwrap_or(name.clone()),
+            };
+            let content_type = field["content_type"]
+                .as_str()
+                .unwrap_or("application/octet-stream");
+            data.extend(
+                format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                    disposition_quote(name),
+                    disposition_quote(filename.as_str()),
+                    content_type
+                )
+                .as_bytes(),
+            );
+            data.extend(async_std::fs::read(path).await?);
+        } else {
+            let value = match field {
+                Value::String(s) => s.clone(),
+                _ => field.to_string(),
+            };
+            data.extend(
+                format!(
+                    "Content-Disposition: form-data; name=\"{}\"\r\n\r\n{}",
+                    disposition_quote(name),
+                    value
+                )
+                .as_bytes(),
+            );
+        }
+        data.extend(b"\r\n");
+    }
+    data.extend(format!("--{}--\r\n", boundary).as_bytes());
+
+    let mut body = Body::from_bytes(data);
+    let mime = Mime::from_str(format!("multipart/form-data; boundary={}", boundary).as_str())
+        .or(Err(err!("110", "invalid multipart boundary")))?;
+    body.set_mime(mime);
+    Ok(body)
+}
+
+/// a quoted `name` or `filename`, `"`, CR and LF percent-encoded like browsers do
+fn disposition_quote(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// same layout as `fstore`: `[<action dir>, <file>]` under the workdir, owned by this task
+fn workdir_file(workdir: Option<&PathBuf>, task_id: &str, path: &Value) -> Result<PathBuf, Error> {
+    let workdir = workdir.ok_or(err!("111", "missing workdir"))?;
+
+    let pav: Vec<&str> = path
+        .as_array()
+        .ok_or(err!("112", "missing path"))?
+        .iter()
+        .filter_map(|p| p.as_str())
+        .collect();
+
+    if pav.is_empty() {
+        return Err(err!("112", "missing path"));
+    }
+    if !check_path(task_id, &pav) {
+        return Err(err!("113", "forbidden access"));
+    }
+
+    let mut file = workdir.clone();
+    for pa in pav {
+        file = file.join(pa);
+    }
+    Ok(file)
+}
+
+/// per-phase timings in milliseconds, phases are sequential so they add up to `total`
+///
+/// without client metrics only `header` and `total` are measured
+fn time_create(metrics: Option<&Metrics>, header: Duration, total: Duration) -> Value {
+    let mut time = Map::new();
+    if let Some(m) = metrics {
+        let ttfb = m
+            .transfer_start_time()
+            .checked_sub(m.name_lookup_time() + m.connect_time() + m.secure_connect_time())
+            .unwrap_or_default();
+        time.insert("dns".into(), millis(m.name_lookup_time()));
+        time.insert("connect".into(), millis(m.connect_time()));
+        time.insert("tls".into(), millis(m.secure_connect_time()));
+        time.insert("ttfb".into(), millis(ttfb));
+        time.insert("transfer".into(), millis(m.transfer_time()));
+    }
+    time.insert("header".into(), millis(header));
+    time.insert("total".into(), millis(total));
+    Value::Object(time)
+}
+
+fn millis(d: Duration) -> Value {
+    let ms = (d.as_micros() as f64) / 1000.0;
+    Number::from_f64(ms)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+/// decode by content type, falling back to text, then base64
+fn body_decode(content_type: Option<Mime>, bytes: Vec<u8>) -> (Value, &'static str) {
+    if bytes.is_empty() {
+        return (Value::Null, "empty");
+    }
+
+    let (essence, subtype) = match content_type.as_ref() {
+        Some(m) => (m.essence().to_owned(), m.subtype().to_owned()),
+        None => (String::new(), String::new()),
+    };
+
+    if essence.is_empty() || essence == "application/json" || subtype.ends_with("+json") {
+        if let Ok(v) = from_slice::<Value>(bytes.as_slice()) {
+            return (v, "json");
+        }
+    }
+
+    let textual = essence.is_empty()
+        || essence.starts_with("text/")
+        || essence == "application/json"
+        || essence == "application/xml"
+        || essence == "application/javascript"
+        || essence == "application/x-www-form-urlencoded"
+        || subtype.ends_with("+json")
+        || subtype.ends_with("+xml");
+
+    if textual {
+        match String::from_utf8(bytes) {
+            Ok(text) => (Value::String(text), "text"),
+            Err(e) => (Value::String(base64::encode(e.into_bytes())), "base64"),
+        }
+    } else {
+        (Value::String(base64::encode(bytes)), "base64")
+    }
+}
+
 struct RestapiError(chord::Error);
 
 impl From<surf::Error> for RestapiError {
@@ -121,3 +363,101 @@ impl From<chord::value::Error> for RestapiError {
         RestapiError(err.into())
     }
 }
+
+impl From<std::io::Error> for RestapiError {
+    fn from(err: std::io::Error) -> Self {
+        RestapiError(cause!("114", err.to_string(), err))
+    }
+}
+
+#[test]
+fn body_decode_test() {
+    let json = Some(Mime::from_str("application/json").unwrap());
+    let text = Some(Mime::from_str("text/plain").unwrap());
+    let png = Some(Mime::from_str("image/png").unwrap());
+
+    assert_eq!((Value::Null, "empty"), body_decode(json.clone(), vec![]));
+    assert_eq!(
+        (json!({"a": 1}), "json"),
+        body_decode(json.clone(), br#"{"a": 1}"#.to_vec())
+    );
+    assert_eq!(
+        (json!({"a": 1}), "json"),
+        body_decode(
+            Some(Mime::from_str("application/problem+json").unwrap()),
+            br#"{"a": 1}"#.to_vec()
+        )
+    );
+    assert_eq!((json!([1]), "json"), body_decode(None, b"[1]".to_vec()));
+    assert_eq!((json!("{a"), "text"), body_decode(json, b"{a".to_vec()));
+    assert_eq!(
+        (json!("[1]"), "text"),
+        body_decode(text.clone(), b"[1]".to_vec())
+    );
+    assert_eq!((json!("/w=="), "base64"), body_decode(text, vec![0xff]));
+    assert_eq!((json!("YWI="), "base64"), body_decode(png, b"ab".to_vec()));
+}
+
+#[test]
+fn workdir_file_test() {
+    let workdir = PathBuf::from("/data/work");
+    let file = |path: Value| workdir_file(Some(&workdir), "1-task", &path);
+
+    assert_eq!(
+        PathBuf::from("/data/work/1-task-step/a.txt"),
+        file(json!(["1-task-step", "a.txt"])).unwrap()
+    );
+    assert_eq!(
+        "113",
+        file(json!(["2-task-step", "a.txt"])).unwrap_err().code()
+    );
+    assert_eq!(
+        "113",
+        file(json!(["1-task-step", ".."])).unwrap_err().code()
+    );
+    assert_eq!(
+        "113",
+        file(json!(["1-task-step", "../a.txt"])).unwrap_err().code()
+    );
+    assert_eq!("113", file(json!(["1-task-step", ""])).unwrap_err().code());
+    assert_eq!("112", file(json!([])).unwrap_err().code());
+    assert_eq!(
+        "111",
+        workdir_file(None, "1-task", &json!(["1-task-step", "a.txt"]))
+            .unwrap_err()
+            .code()
+    );
+}
+
+#[test]
+fn disposition_quote_test() {
+    assert_eq!("a%22b%0D%0Ac.txt", disposition_quote("a\"b\r\nc.txt"));
+}
+
+#[test]
+fn body_create_test() {
+    let create = |body_type: &str, body: Value| {
+        async_std::task::block_on(async {
+            let body = body_create(None, "1-task", body_type, &body).await?;
+            let mime = body.mime().essence().to_string();
+            Ok((mime, body.into_string().await?))
+        })
+        .map_err(|e: RestapiError| e.0.code().to_owned())
+    };
+
+    let form = (
+        "application/x-www-form-urlencoded".to_owned(),
+        "a=1&b=x+y".to_owned(),
+    );
+    assert_eq!(
+        Ok(form.clone()),
+        create("form-urlencoded", json!({"a": 1, "b": "x y"}))
+    );
+    assert_eq!(Ok(form), create("form", json!({"a": 1, "b": "x y"})));
+    assert_eq!(Err("110".to_owned()), create("form-urlencoded", json!([1])));
+    assert_eq!(
+        Ok(("text/plain".to_owned(), "hi".to_owned())),
+        create("text", json!("hi"))
+    );
+    assert_eq!(Err("109".to_owned()), create("xml", json!("<a/>")));
+}
@@ -0,0 +1,332 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use async_native_tls::TlsConnector;
+use async_std::future::timeout;
+use async_std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use chord::action::prelude::*;
+
+pub struct SocketFactory {}
+
+impl SocketFactory {
+    pub async fn new(_: Option<Value>) -> Result<SocketFactory, Error> {
+        Ok(SocketFactory {})
+    }
+}
+
+#[async_trait]
+impl Factory for SocketFactory {
+    async fn create(&self, _: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        Ok(Box::new(Socket {}))
+    }
+}
+
+/// ```yaml
+/// protocol: tcp
+/// address: 127.0.0.1:20880
+/// tls: false
+/// send: "ls\r\n"
+/// encoding: text
+/// read:
+///     delimiter: "dubbo>"
+/// ```
+///
+/// `encoding` of `send` is one of text, hex, base64
+struct Socket {}
+
+#[async_trait]
+impl Action for Socket {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let args = arg.args();
+        let address = args["address"]
+            .as_str()
+            .ok_or(err!("100", "missing address"))?;
+        let payload = match args["send"].as_str() {
+            Some(s) => decode(s, args["encoding"].as_str().unwrap_or("text"))?,
+            None => vec![],
+        };
+        let read = Read::new(&args["read"], arg.timeout())?;
+
+        let data = match args["protocol"].as_str().unwrap_or("tcp") {
+            "tcp" => tcp(address, &args["tls"], payload, read.as_ref()).await?,
+            "udp" => udp(address, payload, read.as_ref()).await?,
+            p => return Err(err!("105", format!("unsupported protocol {}", p))),
+        };
+
+        let value = match data {
+            Some(data) => {
+                let encoding = args["read"]["encoding"].as_str().unwrap_or("text");
+                json!({
+                    "size": data.len(),
+                    "data": encode(data, encoding)?
+                })
+            }
+            None => Value::Null,
+        };
+        Ok(Box::new(value))
+    }
+}
+
+/// ```yaml
+/// read:
+///     delimiter: "\r\n"
+///     delimiter_encoding: text
+///     length: 16
+///     timeout: 3
+///     encoding: text
+/// ```
+///
+/// stops at the first `delimiter` (excluded from the data) or after `length` bytes,
+/// with neither, reads until the peer closes or `timeout` passes without error
+struct Read {
+    delimiter: Option<Vec<u8>>,
+    length: Option<usize>,
+    timeout: Duration,
+}
+
+impl Read {
+    fn new(read: &Value, default_timeout: Duration) -> Result<Option<Read>, Error> {
+        if read.is_null() {
+            return Ok(None);
+        }
+        let delimiter = read["delimiter"]
+            .as_str()
+            .map(|d| decode(d, read["delimiter_encoding"].as_str().unwrap_or("text")))
+            .transpose()?;
+        if let Some(true) = delimiter.as_ref().map(Vec::is_empty) {
+            return Err(err!("100", "empty read.delimiter"));
+        }
+        Ok(Some(Read {
+            delimiter,
+            length: read["length"].as_u64().map(|l| l as usize),
+            timeout: read["timeout"]
+                .as_u64()
+                .map(Duration::from_secs)
+                .unwrap_or(default_timeout),
+        }))
+    }
+
+    fn bounded(&self) -> bool {
+        self.delimiter.is_some() || self.length.is_some()
+    }
+
+    /// end of the data within `buf`, if complete
+    /// whichever of the delimiter and the length comes first
+    fn complete(&self, buf: &[u8]) -> Option<usize> {
+        let delimited = self
+            .delimiter
+            .as_ref()
+            .and_then(|d| buf.windows(d.len()).position(|w| w == d.as_slice()));
+        let length = self.length.filter(|l| buf.len() >= *l);
+        match (delimited, length) {
+            (Some(d), Some(l)) => Some(d.min(l)),
+            (d, l) => d.or(l),
+        }
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// `tls: true`, or `tls: {domain, insecure}`
+async fn tcp(
+    address: &str,
+    tls: &Value,
+    payload: Vec<u8>,
+    read: Option<&Read>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let tcp = TcpStream::connect(address)
+        .await
+        .map_err(|e| err!("101", e.to_string()))?;
+
+    let mut stream: Box<dyn Stream> = if tls.as_bool().unwrap_or(false) || tls.is_object() {
+        let domain = match tls["domain"].as_str() {
+            Some(d) => d.to_owned(),
+            None => host(address),
+        };
+        let insecure = tls["insecure"].as_bool().unwrap_or(false);
+        let connector = TlsConnector::new()
+            .danger_accept_invalid_certs(insecure)
+            .danger_accept_invalid_hostnames(insecure);
+        Box::new(
+            connector
+                .connect(domain.as_str(), tcp)
+                .await
+                .map_err(|e| err!("101", e.to_string()))?,
+        )
+    } else {
+        Box::new(tcp)
+    };
+
+    if !payload.is_empty() {
+        stream.write_all(payload.as_slice()).await?;
+        stream.flush().await?;
+    }
+
+    let read = match read {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+
+    let deadline = Instant::now() + read.timeout;
+    let mut data = vec![];
+    let mut buf = [0u8; 4096];
+    loop {
+        if let Some(end) = read.complete(data.as_slice()) {
+            data.truncate(end);
+            return Ok(Some(data));
+        }
+        let remain = deadline.saturating_duration_since(Instant::now());
+        match timeout(remain, stream.read(&mut buf)).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(n)) => data.extend_from_slice(&buf[..n]),
+            Ok(Err(e)) => return Err(err!("103", e.to_string())),
+            Err(_) => {
+                if read.bounded() {
+                    return Err(err!("104", "read timeout"));
+                }
+                break;
+            }
+        }
+    }
+    if read.bounded() {
+        return Err(err!("104", "connection closed before read complete"));
+    }
+    Ok(Some(data))
+}
+
+/// the host of `host:port`, `[::1]:443` is `::1`
+fn host(address: &str) -> String {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return addr.ip().to_string();
+    }
+    match address.rsplit_once(':') {
+        Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => address,
+    }
+    .to_owned()
+}
+
+/// one datagram is sent, and one datagram is read
+async fn udp(
+    address: &str,
+    payload: Vec<u8>,
+    read: Option<&Read>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let addr = address
+        .to_socket_addrs()
+        .await
+        .map_err(|e| err!("101", e.to_string()))?
+        .next()
+        .ok_or(err!("101", format!("invalid address {}", address)))?;
+    let local = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket
+        .connect(addr)
+        .await
+        .map_err(|e| err!("101", e.to_string()))?;
+    socket.send(payload.as_slice()).await?;
+
+    let read = match read {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+
+    let mut buf = vec![0u8; 65536];
+    let n = timeout(read.timeout, socket.recv(&mut buf))
+        .await
+        .or(Err(err!("104", "read timeout")))??;
+    buf.truncate(n);
+    if let Some(end) = read.complete(buf.as_slice()) {
+        buf.truncate(end);
+    }
+    Ok(Some(buf))
+}
+
+fn decode(text: &str, encoding: &str) -> Result<Vec<u8>, Error> {
+    match encoding {
+        "text" => Ok(text.as_bytes().to_vec()),
+        "hex" => {
+            let text: String = text.split_whitespace().collect();
+            hex::decode(text).or(Err(err!("102", "invalid hex")))
+        }
+        "base64" => base64::decode(text).or(Err(err!("102", "invalid base64"))),
+        _ => Err(err!("105", format!("unsupported encoding {}", encoding))),
+    }
+}
+
+fn encode(data: Vec<u8>, encoding: &str) -> Result<Value, Error> {
+    match encoding {
+        "text" => Ok(Value::String(String::from_utf8_lossy(&data).to_string())),
+        "hex" => Ok(Value::String(hex::encode(data))),
+        "base64" => Ok(Value::String(base64::encode(data))),
+        _ => Err(err!("105", format!("unsupported encoding {}", encoding))),
+    }
+}
+
+#[test]
+fn read_test() {
+    let timeout = Duration::from_secs(1);
+    let read = Read::new(&json!({"delimiter": "\r\n", "length": 8}), timeout)
+        .unwrap()
+        .unwrap();
+    assert_eq!(None, read.complete(b"abc"));
+    assert_eq!(Some(3), read.complete(b"abc\r\ndef"));
+    assert_eq!(Some(8), read.complete(b"abcdefghij"));
+    assert_eq!(Some(8), read.complete(b"abcdefghij\r\n"));
+
+    let read = Read::new(
+        &json!({"delimiter": "0d0a", "delimiter_encoding": "hex"}),
+        timeout,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(Some(1), read.complete(b"a\r\n"));
+    assert!(read.bounded());
+
+    let read = Read::new(&json!({}), timeout).unwrap().unwrap();
+    assert_eq!(None, read.complete(b"abc"));
+    assert!(!read.bounded());
+
+    assert!(Read::new(&Value::Null, timeout).unwrap().is_none());
+    assert_eq!(
+        "100",
+        Read::new(&json!({"delimiter": ""}), timeout)
+            .err()
+            .unwrap()
+            .code()
+    );
+}
+
+#[test]
+fn encoding_test() {
+    assert_eq!(b"ls\r\n".to_vec(), decode("ls\r\n", "text").unwrap());
+    assert_eq!(vec![0xca, 0xfe, 0x01], decode("ca fe\n01", "hex").unwrap());
+    assert_eq!(b"hello".to_vec(), decode("aGVsbG8=", "base64").unwrap());
+    assert_eq!("102", decode("zz", "hex").unwrap_err().code());
+    assert_eq!("102", decode("@@", "base64").unwrap_err().code());
+    assert_eq!("105", decode("a", "utf16").unwrap_err().code());
+
+    assert_eq!(json!("hi"), encode(b"hi".to_vec(), "text").unwrap());
+    assert_eq!(json!("cafe"), encode(vec![0xca, 0xfe], "hex").unwrap());
+    assert_eq!(
+        json!("aGVsbG8="),
+        encode(b"hello".to_vec(), "base64").unwrap()
+    );
+    assert_eq!("105", encode(vec![], "utf16").unwrap_err().code());
+}
+
+#[test]
+fn host_test() {
+    assert_eq!("127.0.0.1", host("127.0.0.1:443"));
+    assert_eq!("::1", host("[::1]:443"));
+    assert_eq!("example.com", host("example.com:443"));
+    assert_eq!("example.com", host("example.com"));
+}
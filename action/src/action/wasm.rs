@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use log::{debug, error, info, trace, warn};
+use surf::http::Method;
+use surf::{Body, Client, RequestBuilder, Url};
+use wasmtime::{
+    Caller, Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+};
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use wasmtime_wasi::WasiCtxBuilder;
+
+use chord::action::prelude::*;
+use chord::value::from_slice;
+
+use crate::action::http;
+use crate::action::http::client::ClientPool;
+
+/// the epoch ticks every `TICK`, a run is interrupted at the first tick past its timeout
+const TICK: Duration = Duration::from_millis(10);
+
+/// ```yaml
+/// wasm:
+///     enable: true
+///     dir: /data/chord/plugin
+///     fuel: 1000000000
+///     memory_limit: 67108864
+///     log: true
+///     http:
+///         hosts: ["127.0.0.1:8080"]
+///         client:
+///             default:
+///                 timeout: 30
+/// ```
+///
+/// `<dir>/<plugin>.wasm` is a wasi module exporting `memory` and
+///
+/// - `chord_alloc(len: i32) -> i32`, room for the input
+/// - `chord_run(ptr: i32, len: i32) -> i64`, the output as `ptr << 32 | len`
+///
+/// the input is `{"id": ..., "args": {...}}`, the output `{"value": ...}` or
+/// `{"error": {"code": ..., "message": ...}}`.
+/// the module runs with no files, env or network of its own, each run in a new instance,
+/// out of `fuel` (104), past the step timeout (105) or growing over `memory_limit` bytes (106) it traps.
+///
+/// with `log: true` the module may import `chord.log(level: i32, ptr: i32, len: i32)`, level `1..5`
+/// is error to trace, with `http` it may import `chord.http(ptr: i32, len: i32) -> i64`.
+/// a request `{"method": ..., "url": ..., "header": {...}, "body": ...}` may only go to `hosts`,
+/// any host if they are left out, the `{"status": ..., "header": {...}, "body": ...}` or `{"error": ...}`
+/// is written to room from `chord_alloc` and returned like the output of `chord_run`
+pub struct WasmFactory {
+    engine: Engine,
+    linker: Arc<Linker<State>>,
+    modules: Mutex<HashMap<String, Module>>,
+    dir: PathBuf,
+    fuel: u64,
+    memory_limit: usize,
+    ticker: Arc<AtomicBool>,
+}
+
+impl WasmFactory {
+    pub async fn new(config: Option<Value>) -> Result<WasmFactory, Error> {
+        let config = config.unwrap_or(Value::Null);
+        let dir = config["dir"].as_str().ok_or(err!("100", "missing dir"))?;
+
+        let mut engine_config = Config::new();
+        engine_config.consume_fuel(true).epoch_interruption(true);
+        let engine = Engine::new(&engine_config)
+            .map_err(|e| err!("100", format!("engine create fail: {}", e)))?;
+
+        let mut linker: Linker<State> = Linker::new(&engine);
+        wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, |s: &mut State| &mut s.wasi)
+            .map_err(|e| err!("100", format!("wasi link fail: {}", e)))?;
+        if config["log"].as_bool().unwrap_or(false) {
+            linker
+                .func_wrap("chord", "log", host_log)
+                .map_err(|e| err!("100", format!("log link fail: {}", e)))?;
+        }
+        if config["http"].is_object() {
+            let client = ClientPool::new(Some(&config["http"]))?.get(None)?;
+            let hosts: Option<Vec<String>> = config["http"]["hosts"].as_array().map(|hosts| {
+                hosts
+                    .iter()
+                    .filter_map(|h| h.as_str().map(str::to_owned))
+                    .collect()
+            });
+            let http = Arc::new(Http { client, hosts });
+            linker
+                .func_wrap(
+                    "chord",
+                    "http",
+                    move |caller: Caller<'_, State>, ptr: i32, len: i32| {
+                        host_http(caller, http.as_ref(), ptr, len)
+                    },
+                )
+                .map_err(|e| err!("100", format!("http link fail: {}", e)))?;
+        }
+
+        // epochs only move when something increments them
+        let ticker = Arc::new(AtomicBool::new(true));
+        let running = ticker.clone();
+        let ticked = engine.clone();
+        thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                thread::sleep(TICK);
+                ticked.increment_epoch();
+            }
+        });
+
+        Ok(WasmFactory {
+            engine,
+            linker: Arc::new(linker),
+            modules: Mutex::new(HashMap::new()),
+            dir: PathBuf::from(dir),
+            fuel: config["fuel"].as_u64().unwrap_or(1_000_000_000),
+            memory_limit: config["memory_limit"]
+                .as_u64()
+                .map_or(64 * 1024 * 1024, |m| m as usize),
+            ticker,
+        })
+    }
+
+    fn module(&self, plugin: &str) -> Result<Module, Error> {
+        let valid = !plugin.is_empty()
+            && plugin
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(err!("101", format!("invalid plugin {}", plugin)));
+        }
+
+        let mut modules = self
+            .modules
+            .lock()
+            .or(Err(err!("101", "plugin cache poisoned")))?;
+        if let Some(module) = modules.get(plugin) {
+            return Ok(module.clone());
+        }
+        let path = self.dir.join(format!("{}.wasm", plugin));
+        trace!("wasm load {:?}", path);
+        let module = Module::from_file(&self.engine, &path)
+            .map_err(|e| err!("101", format!("plugin {} load fail: {}", plugin, e)))?;
+        modules.insert(plugin.to_owned(), module.clone());
+        Ok(module)
+    }
+}
+
+impl Drop for WasmFactory {
+    fn drop(&mut self) {
+        self.ticker.store(false, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl Factory for WasmFactory {
+    async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        let plugin = arg.args()["plugin"]
+            .as_str()
+            .ok_or(err!("010", "missing plugin"))?;
+        let plugin = arg.render_str(plugin)?;
+        let module = self.module(plugin.as_str())?;
+
+        Ok(Box::new(Wasm {
+            engine: self.engine.clone(),
+            linker: self.linker.clone(),
+            module,
+            fuel: self.fuel,
+            memory_limit: self.memory_limit,
+        }))
+    }
+}
+
+struct Wasm {
+    engine: Engine,
+    linker: Arc<Linker<State>>,
+    module: Module,
+    fuel: u64,
+    memory_limit: usize,
+}
+
+struct State {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+    id: String,
+}
+
+/// ```yaml
+/// plugin: hello
+/// name: "{{case.name}}"
+/// ```
+#[async_trait]
+impl Action for Wasm {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let output = run0(self, arg.id().to_string(), arg.args(), arg.timeout()).await?;
+        if output["error"].is_object() {
+            let code = output["error"]["code"].as_str().unwrap_or("plugin");
+            let message = output["error"]["message"].as_str().unwrap_or("");
+            return Err(err!(code, message));
+        }
+        Ok(Box::new(output["value"].clone()))
+    }
+}
+
+async fn run0(wasm: &Wasm, id: String, args: &Value, timeout: Duration) -> Result<Value, Error> {
+    let input = json!({
+        "id": id,
+        "args": args
+    })
+    .to_string();
+
+    let state = State {
+        wasi: WasiCtxBuilder::new().build_p1(),
+        limits: StoreLimitsBuilder::new()
+            .memory_size(wasm.memory_limit)
+            .instances(1)
+            .trap_on_grow_failure(true)
+            .build(),
+        id,
+    };
+    let ticks = (timeout.as_millis() / TICK.as_millis()) as u64 + 1;
+    let engine = wasm.engine.clone();
+    let linker = wasm.linker.clone();
+    let module = wasm.module.clone();
+    let fuel = wasm.fuel;
+
+    // wasm runs to the end or a trap, off the executor
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let mut store = Store::new(&engine, state);
+        store.limiter(|s| &mut s.limits);
+        store.epoch_deadline_trap();
+        store.set_epoch_deadline(ticks);
+        let res = store
+            .set_fuel(fuel)
+            .map_err(|e| err!("102", e.to_string()))
+            .and_then(|_| call(&mut store, &linker, &module, input.as_str()));
+        let _ = tx.send(res);
+    });
+    rx.await.or(Err(err!("106", "plugin aborted")))?
+}
+
+fn call(
+    store: &mut Store<State>,
+    linker: &Linker<State>,
+    module: &Module,
+    input: &str,
+) -> Result<Value, Error> {
+    let instance = linker
+        .instantiate(&mut *store, module)
+        .map_err(|e| err!("102", format!("instantiate fail: {}", e)))?;
+    if let Ok(init) = instance.get_typed_func::<(), ()>(&mut *store, "_initialize") {
+        init.call(&mut *store, ()).map_err(trap)?;
+    }
+
+    let memory = instance
+        .get_memory(&mut *store, "memory")
+        .ok_or(err!("103", "missing export memory"))?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&mut *store, "chord_alloc")
+        .map_err(|e| err!("103", format!("chord_alloc: {}", e)))?;
+    let run = instance
+        .get_typed_func::<(i32, i32), i64>(&mut *store, "chord_run")
+        .map_err(|e| err!("103", format!("chord_run: {}", e)))?;
+
+    let len = input.len() as i32;
+    let ptr = alloc.call(&mut *store, len).map_err(trap)?;
+    memory
+        .write(&mut *store, ptr as u32 as usize, input.as_bytes())
+        .map_err(|e| err!("103", format!("input write fail: {}", e)))?;
+
+    let packed = run.call(&mut *store, (ptr, len)).map_err(trap)?;
+    let (ptr, len) = unpack(packed);
+    let output = ptr
+        .checked_add(len)
+        .and_then(|end| memory.data(&*store).get(ptr..end))
+        .ok_or(err!("103", "output out of bounds"))?;
+    from_slice(output).map_err(|e| err!("103", format!("output is not json: {}", e)))
+}
+
+fn trap(e: wasmtime::Error) -> Error {
+    match e.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => err!("104", "fuel exhausted"),
+        Some(Trap::Interrupt) => err!("105", "timeout"),
+        _ => err!("106", e.root_cause().to_string()),
+    }
+}
+
+fn unpack(packed: i64) -> (usize, usize) {
+    (
+        (packed as u64 >> 32) as usize,
+        (packed as u64 & 0xffff_ffff) as usize,
+    )
+}
+
+fn pack(ptr: i32, len: usize) -> i64 {
+    ((ptr as u32 as u64) << 32 | len as u64) as i64
+}
+
+fn memory_read(caller: &mut Caller<'_, State>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("missing export memory"))?;
+    let (ptr, len) = (ptr as u32 as usize, len as u32 as usize);
+    ptr.checked_add(len)
+        .and_then(|end| memory.data(&*caller).get(ptr..end))
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmtime::Error::msg("out of bounds memory read"))
+}
+
+/// writes `data` into room from the module's `chord_alloc`
+fn memory_write(caller: &mut Caller<'_, State>, data: &[u8]) -> wasmtime::Result<i64> {
+    let alloc = caller
+        .get_export("chord_alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| wasmtime::Error::msg("missing export chord_alloc"))?
+        .typed::<i32, i32>(&*caller)?;
+    let ptr = alloc.call(&mut *caller, data.len() as i32)?;
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("missing export memory"))?;
+    memory.write(&mut *caller, ptr as u32 as usize, data)?;
+    Ok(pack(ptr, data.len()))
+}
+
+fn host_log(mut caller: Caller<'_, State>, level: i32, ptr: i32, len: i32) -> wasmtime::Result<()> {
+    let message = memory_read(&mut caller, ptr, len)?;
+    let message = String::from_utf8_lossy(&message);
+    let id = caller.data().id.as_str();
+    match level {
+        1 => error!("{} {}", id, message),
+        2 => warn!("{} {}", id, message),
+        3 => info!("{} {}", id, message),
+        4 => debug!("{} {}", id, message),
+        _ => trace!("{} {}", id, message),
+    }
+    Ok(())
+}
+
+struct Http {
+    client: Client,
+    hosts: Option<Vec<String>>,
+}
+
+fn host_http(
+    mut caller: Caller<'_, State>,
+    http: &Http,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<i64> {
+    let request = memory_read(&mut caller, ptr, len)?;
+    // the module blocks on the answer like a wasi read
+    let response = match from_slice::<Value>(&request) {
+        Ok(request) => async_std::task::block_on(http_send(http, &request)),
+        Err(e) => Err(err!("107", format!("request is not json: {}", e))),
+    };
+    let response = match response {
+        Ok(r) => r,
+        Err(e) => json!({
+            "error": {
+                "code": e.code(),
+                "message": e.message()
+            }
+        }),
+    };
+    memory_write(&mut caller, response.to_string().as_bytes())
+}
+
+async fn http_send(http: &Http, request: &Value) -> Result<Value, Error> {
+    let url = request["url"].as_str().ok_or(err!("107", "missing url"))?;
+    let url = Url::from_str(url).or(Err(err!("107", format!("invalid url: {}", url))))?;
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+        None => url.host_str().unwrap_or("").to_owned(),
+    };
+    if let Some(hosts) = http.hosts.as_ref() {
+        if !hosts.contains(&host) {
+            return Err(err!("108", format!("forbidden host {}", host)));
+        }
+    }
+    let method = request["method"].as_str().unwrap_or("GET");
+    let method = Method::from_str(method).or(Err(err!("107", "invalid method")))?;
+
+    let mut rb = RequestBuilder::new(method, url);
+    rb = http::header_apply(rb, &request["header"])?;
+    match &request["body"] {
+        Value::Null => {}
+        Value::String(body) => rb = rb.body(Body::from_string(body.clone())),
+        body => rb = rb.body(Body::from_json(body).map_err(|e| err!("107", e.to_string()))?),
+    }
+
+    let mut res = http
+        .client
+        .send(rb.build())
+        .await
+        .map_err(|e| err!("109", e.to_string()))?;
+    let mut header = Map::new();
+    for (hn, hv) in res.iter() {
+        header.insert(
+            hn.to_string(),
+            Value::Array(hv.iter().map(|v| Value::String(v.to_string())).collect()),
+        );
+    }
+    let body = res
+        .body_string()
+        .await
+        .map_err(|e| err!("109", e.to_string()))?;
+    Ok(json!({
+        "status": u16::from(res.status()),
+        "header": header,
+        "body": body
+    }))
+}
+
+#[test]
+fn pack_test() {
+    assert_eq!((65536, 12), unpack(pack(65536, 12)));
+    assert_eq!((0x8000_0000, 1), unpack(pack(0x8000_0000u32 as i32, 1)));
+}
+
+#[test]
+fn run_test() {
+    let dir = std::env::temp_dir().join(format!("chord_wasm_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let plugins = [
+        (
+            "echo",
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{\"value\":1}")
+                (func (export "chord_alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "chord_run") (param i32 i32) (result i64) i64.const 11))"#,
+        ),
+        (
+            "spin",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "chord_alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "chord_run") (param i32 i32) (result i64)
+                    (loop $l (br $l))
+                    i64.const 0))"#,
+        ),
+        (
+            "grow",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "chord_alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "chord_run") (param i32 i32) (result i64)
+                    (drop (memory.grow (i32.const 4096)))
+                    i64.const 0))"#,
+        ),
+        (
+            "wild",
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "chord_alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "chord_run") (param i32 i32) (result i64)
+                    i64.const 0x7fff00007fffffff))"#,
+        ),
+    ];
+    for (name, text) in plugins.iter() {
+        let wasm = wat::parse_str(text).unwrap();
+        std::fs::write(dir.join(format!("{}.wasm", name)), wasm).unwrap();
+    }
+
+    let factory = async_std::task::block_on(WasmFactory::new(Some(json!({
+        "dir": dir.to_str(),
+        "fuel": 10_000_000,
+        "memory_limit": 1024 * 1024
+    }))))
+    .unwrap();
+    let run = |plugin: &str, fuel: u64, timeout: u64| {
+        let wasm = Wasm {
+            engine: factory.engine.clone(),
+            linker: factory.linker.clone(),
+            module: factory.module(plugin).unwrap(),
+            fuel,
+            memory_limit: factory.memory_limit,
+        };
+        let timeout = Duration::from_millis(timeout);
+        async_std::task::block_on(run0(&wasm, "id".into(), &json!({}), timeout))
+            .map_err(|e| e.code().to_owned())
+    };
+
+    assert_eq!(Ok(json!({"value": 1})), run("echo", factory.fuel, 1000));
+    assert_eq!(Err("104".to_owned()), run("spin", factory.fuel, 60000));
+    assert_eq!(Err("105".to_owned()), run("spin", u64::MAX, 100));
+    assert_eq!(Err("106".to_owned()), run("grow", factory.fuel, 1000));
+    assert_eq!(Err("103".to_owned()), run("wild", factory.fuel, 1000));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,273 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::future::timeout;
+use async_std::sync::Mutex;
+use async_tungstenite::async_std::{connect_async, ConnectStream};
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use async_tungstenite::tungstenite::http::header::{HeaderName, HeaderValue};
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::WebSocketStream;
+use futures::{SinkExt, StreamExt};
+
+use chord::action::prelude::*;
+use chord::value::from_str;
+
+pub struct WebsocketFactory {}
+
+impl WebsocketFactory {
+    pub async fn new(_: Option<Value>) -> Result<WebsocketFactory, Error> {
+        Ok(WebsocketFactory {})
+    }
+}
+
+#[async_trait]
+impl Factory for WebsocketFactory {
+    async fn create(&self, _: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        Ok(Box::new(Websocket {}))
+    }
+}
+
+struct Websocket {}
+
+/// a connection kept in the case session
+struct Connection(Mutex<WebSocketStream<ConnectStream>>);
+
+#[async_trait]
+impl Action for Websocket {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        run0(arg).await.map(|v| Box::new(v) as Box<dyn Scope>)
+    }
+}
+
+/// ```yaml
+/// url: ws://127.0.0.1:8080/push
+/// header:
+///     Authorization: "Bearer {{case.token}}"
+/// connection: push
+/// script:
+///     - send: { "op": "subscribe", "topic": "{{case.topic}}" }
+///     - expect: (eq curr.value.op "subscribed")
+///       timeout: 5
+/// ```
+///
+/// with `connection`, the socket is kept in the case session under that name,
+/// later steps with the same `connection` reuse it and may omit `url`, `close: true` closes it.
+/// every received frame is collected into the value, text frames are parsed as json when possible,
+/// binary frames are base64
+async fn run0(arg: &dyn RunArg) -> Result<Value, Error> {
+    let args = arg.args();
+    let session = arg.case_session();
+    let key = args["connection"]
+        .as_str()
+        .map(|c| format!("websocket:{}", c));
+
+    let conn = match key.as_ref().and_then(|k| session.resource(k)) {
+        Some(r) => r
+            .downcast::<Connection>()
+            .or(Err(err!("100", "connection is not a websocket")))?,
+        None => {
+            let conn = Arc::new(connect(args).await?);
+            if let Some(k) = key.as_ref() {
+                session.set_resource(k, Some(conn.clone()));
+            }
+            conn
+        }
+    };
+
+    let mut ws = conn.0.lock().await;
+    let mut frames = vec![];
+    let script = args["script"].as_array().map_or(&[][..], Vec::as_slice);
+    let assert = |condition: &str, frame: &Value| arg.assert(condition, frame);
+    let result = script_run(script, arg.timeout(), &assert, &mut ws, &mut frames).await;
+
+    let close = key.is_none() || args["close"].as_bool().unwrap_or(false);
+    if close || result.is_err() {
+        let _ = ws.close(None).await;
+        if let Some(k) = key.as_ref() {
+            session.set_resource(k, None);
+        }
+    }
+
+    result?;
+    Ok(Value::Array(frames))
+}
+
+async fn connect(args: &Value) -> Result<Connection, Error> {
+    let url = args["url"].as_str().ok_or(err!("100", "missing url"))?;
+    let mut request = url
+        .into_client_request()
+        .or(Err(err!("101", format!("invalid url: {}", url))))?;
+
+    if let Some(header) = args["header"].as_object() {
+        for (k, v) in header.iter() {
+            let hn =
+                HeaderName::from_bytes(k.as_bytes()).or(Err(err!("106", "invalid header name")))?;
+            let hv = match v {
+                Value::String(s) => HeaderValue::from_str(s),
+                _ => HeaderValue::from_str(v.to_string().as_str()),
+            }
+            .or(Err(err!("106", "invalid header value")))?;
+            request.headers_mut().append(hn, hv);
+        }
+    }
+
+    let (ws, _) = connect_async(request)
+        .await
+        .map_err(|e| err!("101", e.to_string()))?;
+    Ok(Connection(Mutex::new(ws)))
+}
+
+/// `expect` waits `timeout` seconds, the step timeout by default
+async fn script_run(
+    script: &[Value],
+    default_timeout: Duration,
+    assert: &(dyn Fn(&str, &Value) -> bool + Sync),
+    ws: &mut WebSocketStream<ConnectStream>,
+    frames: &mut Vec<Value>,
+) -> Result<(), Error> {
+    for item in script {
+        if !item["send"].is_null() {
+            let message = message_create(item)?;
+            ws.send(message)
+                .await
+                .map_err(|e| err!("105", e.to_string()))?;
+        } else if let Some(condition) = item["expect"].as_str() {
+            let duration = item["timeout"]
+                .as_u64()
+                .map(Duration::from_secs)
+                .unwrap_or(default_timeout);
+            timeout(duration, expect(assert, ws, condition, frames))
+                .await
+                .or(Err(err!("103", format!("expect timeout: {}", condition))))??;
+        } else {
+            return Err(err!("102", "script item must be send or expect"));
+        }
+    }
+    Ok(())
+}
+
+async fn expect(
+    assert: &(dyn Fn(&str, &Value) -> bool + Sync),
+    ws: &mut WebSocketStream<ConnectStream>,
+    condition: &str,
+    frames: &mut Vec<Value>,
+) -> Result<(), Error> {
+    loop {
+        let message = match ws.next().await {
+            Some(m) => m.map_err(|e| err!("104", e.to_string()))?,
+            None => return Err(err!("104", "connection closed")),
+        };
+        let frame = match message {
+            Message::Text(text) => from_str(text.as_str()).unwrap_or(Value::String(text)),
+            Message::Binary(bytes) => Value::String(base64::encode(bytes)),
+            Message::Close(_) => return Err(err!("104", "connection closed")),
+            _ => continue,
+        };
+        let matched = assert(condition, &frame);
+        frames.push(frame);
+        if matched {
+            return Ok(());
+        }
+    }
+}
+
+/// `send` strings go as text, other values as json text, `binary: true` sends base64 decoded bytes
+fn message_create(item: &Value) -> Result<Message, Error> {
+    let send = &item["send"];
+    if item["binary"].as_bool().unwrap_or(false) {
+        let encoded = send
+            .as_str()
+            .ok_or(err!("102", "binary send must be base64 string"))?;
+        let bytes = base64::decode(encoded).or(Err(err!("102", "binary send must be base64")))?;
+        return Ok(Message::Binary(bytes));
+    }
+    match send {
+        Value::String(s) => Ok(Message::Text(s.clone())),
+        _ => Ok(Message::Text(send.to_string())),
+    }
+}
+
+#[test]
+fn script_run_test() {
+    use async_std::net::TcpListener;
+
+    async_std::task::block_on(async {
+        // echoes text, answers binary with its length
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        async_std::task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = async_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(message)) = ws.next().await {
+                let reply = match message {
+                    Message::Text(text) => Message::Text(text),
+                    Message::Binary(bytes) => {
+                        Message::Text(json!({ "len": bytes.len() }).to_string())
+                    }
+                    _ => break,
+                };
+                ws.send(Message::Text("\"noise\"".into())).await.unwrap();
+                ws.send(reply).await.unwrap();
+            }
+        });
+
+        let conn = connect(&json!({ "url": format!("ws://{}", addr) }))
+            .await
+            .unwrap();
+        let mut ws = conn.0.lock().await;
+        // the condition names a field the frame must have
+        let assert = |condition: &str, frame: &Value| !frame[condition].is_null();
+        let timeout = Duration::from_secs(5);
+
+        let script = json!([
+            {"send": {"op": "subscribe"}},
+            {"expect": "op"},
+            {"send": "AAEC", "binary": true},
+            {"expect": "len"}
+        ]);
+        let mut frames = vec![];
+        script_run(
+            script.as_array().unwrap(),
+            timeout,
+            &assert,
+            &mut ws,
+            &mut frames,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![
+                json!("noise"),
+                json!({"op": "subscribe"}),
+                json!("noise"),
+                json!({"len": 3})
+            ],
+            frames
+        );
+
+        let script = json!([{"send": "1"}, {"expect": "never", "timeout": 1}]);
+        let e = script_run(
+            script.as_array().unwrap(),
+            timeout,
+            &assert,
+            &mut ws,
+            &mut vec![],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!("103", e.code());
+
+        let script = json!([{"wait": 1}]);
+        let e = script_run(
+            script.as_array().unwrap(),
+            timeout,
+            &assert,
+            &mut ws,
+            &mut vec![],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!("102", e.code());
+    });
+}
@@ -0,0 +1,32 @@
+use chord::flow::ID_PATTERN;
+
+/// whether `[<action dir>, <name>...]` stays inside an action dir of the task
+///
+/// action dirs are `<task id>-<step id>` and step ids are word characters only,
+/// so task `t1` never reaches the dirs of `t10` or `t1-x`
+pub fn check_path(task_id: &str, path: &[&str]) -> bool {
+    let step = path
+        .first()
+        .and_then(|d| d.strip_prefix(task_id))
+        .and_then(|d| d.strip_prefix('-'));
+    match step {
+        Some(step) if ID_PATTERN.is_match(step) => path
+            .iter()
+            .all(|p| !p.is_empty() && *p != "." && *p != ".." && !p.contains(&['/', '\\'][..])),
+        _ => false,
+    }
+}
+
+#[test]
+fn check_path_test() {
+    assert!(check_path("e-t1", &["e-t1-s1"]));
+    assert!(check_path("e-t1", &["e-t1-s1", "a", "b.txt"]));
+    assert!(!check_path("e-t1", &[]));
+    assert!(!check_path("e-t1", &["e-t1"]));
+    assert!(!check_path("e-t1", &["e-t1-"]));
+    assert!(!check_path("e-t1", &["e-t10-s1"]));
+    assert!(!check_path("e-t1", &["e-t1-x-s1"]));
+    assert!(!check_path("e-t1", &["e-t1-s1", ".."]));
+    assert!(!check_path("e-t1", &["e-t1-s1", "a/../.."]));
+    assert!(!check_path("e-t1", &["e-t1-s1", ""]));
+}
@@ -1,4 +1,7 @@
+use std::any::Any;
 use std::fmt::Display;
+use std::path::Path;
+use std::sync::Arc;
 
 pub use async_trait::async_trait;
 
@@ -20,6 +23,7 @@ pub mod prelude {
     pub use super::Factory;
     pub use super::RunArg;
     pub use super::Scope;
+    pub use super::Session;
 }
 
 pub trait Scope: Sync + Send {
@@ -31,7 +35,42 @@ pub trait RunArg: Sync + Send {
 
     fn args(&self) -> &Value;
 
+    /// the render context, `case`, `step`, `def` and `pre`
+    fn context(&self) -> &Value;
+
+    /// the task dir
+    fn dir(&self) -> &Path;
+
     fn timeout(&self) -> Duration;
+
+    /// shared by steps in a case
+    fn case_session(&self) -> &dyn Session;
+
+    /// shared by cases in a task
+    fn task_session(&self) -> &dyn Session;
+
+    /// evaluates `condition` like a step assert, with `curr.value` bound to `value`
+    fn assert(&self, condition: &str, value: &Value) -> bool;
+}
+
+pub trait Session: Sync + Send {
+    fn get(&self, key: &str) -> Value;
+
+    fn set(&self, key: &str, value: Value);
+
+    fn update(&self, key: &str, f: &mut dyn FnMut(&mut Value));
+
+    /// connections and other live objects, dropped with the session
+    fn resource(&self, key: &str) -> Option<Arc<dyn Any + Sync + Send>>;
+
+    fn set_resource(&self, key: &str, resource: Option<Arc<dyn Any + Sync + Send>>);
+
+    /// the resource of `key`, or the one made by `f` and kept, looked up and kept under one lock
+    fn resource_or_insert_with(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut() -> Result<Arc<dyn Any + Sync + Send>, Error>,
+    ) -> Result<Arc<dyn Any + Sync + Send>, Error>;
 }
 
 pub trait CreateArg: Sync + Send {
@@ -43,6 +82,9 @@ pub trait CreateArg: Sync + Send {
 
     fn render_str(&self, text: &str) -> Result<String, Error>;
 
+    /// the task dir
+    fn dir(&self) -> &Path;
+
     /// shared in whole action
     fn is_shared(&self, text: &str) -> bool;
 }
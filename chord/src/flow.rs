@@ -1,5 +1,6 @@
 use std::borrow::Borrow;
 use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use lazy_static::lazy_static;
@@ -16,11 +17,16 @@ lazy_static! {
 #[derive(Debug, Clone)]
 pub struct Flow {
     flow: Value,
+    dir: PathBuf,
 }
 
 impl Flow {
-    pub fn new(flow: Value) -> Result<Flow, Error> {
-        let flow = Flow { flow };
+    /// `dir` is the task dir `flow.yml` was loaded from
+    pub fn new(flow: Value, dir: &Path) -> Result<Flow, Error> {
+        let flow = Flow {
+            flow,
+            dir: dir.to_path_buf(),
+        };
 
         flow._version()?;
 
@@ -75,6 +81,10 @@ impl Flow {
         return Ok(flow);
     }
 
+    pub fn dir(&self) -> &Path {
+        self.dir.as_path()
+    }
+
     pub fn version(&self) -> &str {
         self._version().unwrap()
     }
@@ -120,6 +130,10 @@ impl Flow {
         self.step(step_id)["assert"].as_str()
     }
 
+    pub fn step_assert_lua(&self, step_id: &str) -> Option<&str> {
+        self.step(step_id)["assert_lua"].as_str()
+    }
+
     pub fn step_timeout(&self, step_id: &str) -> Duration {
         self._step_timeout(step_id).unwrap()
     }
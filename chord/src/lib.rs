@@ -6,6 +6,8 @@ pub mod collection;
 mod error;
 pub mod flow;
 pub mod input;
+#[cfg(feature = "lua")]
+pub mod lua;
 pub mod output;
 pub mod step;
 pub mod task;
@@ -0,0 +1,283 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub use rlua;
+use rlua::{HookTriggers, StdLib};
+
+use crate::err;
+use crate::error::Error;
+use crate::value::{Map, Number, Value};
+
+/// proxies a table so scripts can read but not change it, `__chord` keeps the table for `to_value`,
+/// `__metatable` hides it from `getmetatable`
+const READ_ONLY: &str = r#"
+local function read_only(t)
+    if type(t) ~= "table" then
+        return t
+    end
+    return setmetatable({}, {
+        __chord = t,
+        __metatable = false,
+        __index = function(_, k) return read_only(t[k]) end,
+        __newindex = function() error("read only", 2) end,
+        __len = function() return #t end,
+        __pairs = function()
+            return function(_, k)
+                local nk, v = next(t, k)
+                return nk, read_only(v)
+            end, nil, nil
+        end
+    })
+end
+return read_only
+"#;
+
+/// `memory_limit` in bytes and `instruction_limit` of a run, the same config for every script
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    pub memory: usize,
+    pub instruction: u64,
+}
+
+impl Default for Limit {
+    fn default() -> Self {
+        Limit {
+            memory: 1024000,
+            instruction: 100000000,
+        }
+    }
+}
+
+impl Limit {
+    /// the limits of `conf` in place of these
+    pub fn with(self, conf: &Value) -> Limit {
+        Limit {
+            memory: conf["memory_limit"]
+                .as_u64()
+                .map_or(self.memory, |m| m as usize),
+            instruction: conf["instruction_limit"]
+                .as_u64()
+                .unwrap_or(self.instruction),
+        }
+    }
+
+    /// the limits of `conf` where they are lower, so a step can not raise them
+    pub fn within(self, conf: &Value) -> Limit {
+        let limit = self.with(conf);
+        Limit {
+            memory: limit.memory.min(self.memory),
+            instruction: limit.instruction.min(self.instruction),
+        }
+    }
+}
+
+/// the libs without io, os, package, `dofile` and `loadfile`, `require` only reads the task `dir`
+pub fn sandbox(dir: &Path) -> Result<rlua::Lua, Error> {
+    let rt = rlua::Lua::new_with(
+        StdLib::BASE | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH,
+    );
+    let dir = dir.to_path_buf();
+    rt.context(|lua| -> Result<(), Error> {
+        lua.set_named_registry_value("chord_loaded", lua.create_table()?)?;
+        let read_only: rlua::Function = lua.load(READ_ONLY).eval()?;
+        lua.set_named_registry_value("chord_read_only", read_only)?;
+        let require = lua.create_function(move |lua, name: String| require(lua, &dir, name))?;
+        lua.globals().set("require", require)?;
+        lua.globals().set("dofile", rlua::Nil)?;
+        lua.globals().set("loadfile", rlua::Nil)?;
+        Ok(())
+    })?;
+    Ok(rt)
+}
+
+/// applies to the runs until the next call,
+/// a script can not be dropped at a timeout like a future, so a hook stops it
+pub fn limit(rt: &rlua::Lua, limit: Limit, timeout: Duration) {
+    rt.set_memory_limit(Some(limit.memory));
+
+    let deadline = Instant::now() + timeout;
+    let mut count: u64 = 0;
+    rt.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(1000),
+            ..Default::default()
+        },
+        move |_, _| {
+            count += 1000;
+            if count > limit.instruction {
+                return Err(rlua::Error::RuntimeError(
+                    "instruction limit exceeded".into(),
+                ));
+            }
+            if Instant::now() > deadline {
+                return Err(rlua::Error::RuntimeError("timeout".into()));
+            }
+            Ok(())
+        },
+    );
+}
+
+/// `case`, `step`, `def`, `pre` and `curr` of the render `context` as read only globals
+pub fn context_set(lua: rlua::Context, context: &Value) -> Result<(), Error> {
+    let read_only: rlua::Function = lua.named_registry_value("chord_read_only")?;
+    for key in ["case", "step", "def", "pre", "curr"] {
+        let v = rlua_serde::to_value(lua, &context[key])?;
+        let v: rlua::Value = read_only.call(v)?;
+        lua.globals().set(key, v)?;
+    }
+    Ok(())
+}
+
+pub fn from_value<'lua>(
+    lua: rlua::Context<'lua>,
+    value: &Value,
+) -> Result<rlua::Value<'lua>, Error> {
+    Ok(rlua_serde::to_value(lua, value)?)
+}
+
+fn require<'lua>(
+    lua: rlua::Context<'lua>,
+    dir: &Path,
+    name: String,
+) -> rlua::Result<rlua::Value<'lua>> {
+    let loaded: rlua::Table = lua.named_registry_value("chord_loaded")?;
+    let module: rlua::Value = loaded.get(name.as_str())?;
+    if !matches!(module, rlua::Value::Nil) {
+        return Ok(module);
+    }
+
+    let valid = name
+        .split('.')
+        .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+    if !valid {
+        return Err(rlua::Error::RuntimeError(format!(
+            "invalid module {}",
+            name
+        )));
+    }
+    let path = dir.join(format!("{}.lua", name.replace('.', "/")));
+    let code = std::fs::read_to_string(&path)
+        .map_err(|e| rlua::Error::RuntimeError(format!("module {} load fail: {}", name, e)))?;
+
+    let module: rlua::Value = lua.load(code.as_str()).set_name(name.as_str())?.eval()?;
+    let module = match module {
+        rlua::Value::Nil => rlua::Value::Boolean(true),
+        m => m,
+    };
+    loaded.set(name.as_str(), module.clone())?;
+    Ok(module)
+}
+
+/// tables with keys `1..n` are arrays
+pub fn to_value(lua_value: &rlua::Value) -> Result<Value, Error> {
+    to_value0(lua_value, 0)
+}
+
+fn to_value0(lua_value: &rlua::Value, depth: usize) -> Result<Value, Error> {
+    if depth > 64 {
+        return Err(err!("102", "invalid value, nested too deep"));
+    }
+    match lua_value {
+        rlua::Value::Nil => Ok(Value::Null),
+        rlua::Value::String(v) => Ok(Value::String(v.to_str()?.to_string())),
+        rlua::Value::Integer(v) => Ok(Value::Number(Number::from(*v))),
+        rlua::Value::Boolean(v) => Ok(Value::Bool(*v)),
+
+        rlua::Value::Number(v) => Ok(Number::from_f64(*v).map_or(Value::Null, Value::Number)),
+        rlua::Value::Table(v) => {
+            if let Some(meta) = v.get_metatable() {
+                let origin: rlua::Value = meta.raw_get("__chord")?;
+                if let rlua::Value::Table(_) = origin {
+                    return to_value0(&origin, depth);
+                }
+            }
+
+            let pairs = v
+                .clone()
+                .pairs::<rlua::Value, rlua::Value>()
+                .collect::<Result<Vec<_>, _>>()?;
+            let len = v.raw_len() as usize;
+            if len > 0 && len == pairs.len() {
+                let mut vec = Vec::with_capacity(len);
+                for i in 1..=len {
+                    let v: rlua::Value = v.raw_get(i)?;
+                    vec.push(to_value0(&v, depth + 1)?);
+                }
+                return Ok(Value::Array(vec));
+            }
+
+            let mut map = Map::new();
+            for (k, v) in pairs {
+                let k = match k {
+                    rlua::Value::String(k) => k.to_str()?.to_string(),
+                    rlua::Value::Integer(k) => k.to_string(),
+                    rlua::Value::Number(k) => k.to_string(),
+                    _ => return Err(err!("102", "invalid key")),
+                };
+                map.insert(k, to_value0(&v, depth + 1)?);
+            }
+            Ok(Value::Object(map))
+        }
+
+        _ => Err(err!("102", "invalid value")),
+    }
+}
+
+#[test]
+fn to_value_test() {
+    let rt = sandbox(Path::new(".")).unwrap();
+    rt.context(|lua| {
+        let v: rlua::Value = lua
+            .load(r#"return { {a = 1}, "b", { x = {1, 2}, y = {} } }"#)
+            .eval()
+            .unwrap();
+        assert_eq!(
+            crate::value::json!([{"a": 1}, "b", {"x": [1, 2], "y": {}}]),
+            to_value(&v).unwrap()
+        );
+    });
+}
+
+#[test]
+fn sandbox_test() {
+    let rt = sandbox(Path::new(".")).unwrap();
+    rt.context(|lua| {
+        context_set(lua, &crate::value::json!({"case": {"a": [1, 2]}})).unwrap();
+        let v: rlua::Value = lua
+            .load(
+                r#"
+                assert(dofile == nil and loadfile == nil)
+                assert(getmetatable(case) == false)
+                assert(not pcall(setmetatable, case, {}))
+                assert(not pcall(function() case.a = 1 end))
+                return case
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(crate::value::json!({"a": [1, 2]}), to_value(&v).unwrap());
+    });
+}
+
+#[test]
+fn limit_test() {
+    let conf = Limit::default().with(&crate::value::json!({ "memory_limit": 2048 }));
+    assert_eq!(
+        Limit {
+            memory: 2048,
+            instruction: 100000000
+        },
+        conf
+    );
+    assert_eq!(
+        Limit {
+            memory: 1024,
+            instruction: 100000000
+        },
+        conf.within(&crate::value::json!({ "memory_limit": 1024, "instruction_limit": u64::MAX }))
+    );
+    assert_eq!(
+        conf,
+        conf.within(&crate::value::json!({ "memory_limit": 4096 }))
+    );
+}
@@ -106,7 +106,7 @@ async fn task_run0<P: AsRef<Path>>(
 
     let flow_file = task_path.clone().join("flow.yml");
     let flow = chord_input::load::flow::yml::load(&flow_file)?;
-    let flow = Flow::new(flow)?;
+    let flow = Flow::new(flow, task_path.as_ref())?;
 
     //read
     let data_file_path = task_path.clone().join("case.csv");
@@ -43,6 +43,7 @@ async fn main() -> Result<(), Error> {
     let flow_ctx = chord_flow::context_create(
         Box::new(FactoryComposite::new(config.action().map(|c| c.clone())).await?),
         Box::new(YmlFlowParser::new()),
+        config.action().map(|c| &c["lua"]),
     )
     .await;
     let task_state_vec = job::run(input_dir, opt.task, exec_id, flow_ctx, &config).await?;
@@ -10,7 +10,7 @@ use chord::task::TaskId;
 use chord::value::Value;
 use chord::value::{to_value, Map};
 
-use crate::flow::step::arg::RunArgStruct;
+use crate::flow::step::arg::{RunArgStruct, SessionStruct};
 use crate::model::app::Context;
 use crate::model::app::RenderContext;
 use chord::Error;
@@ -57,15 +57,19 @@ pub struct CaseArgStruct {
     step_vec: Arc<TailDropVec<(String, Box<dyn Action>)>>,
     data: Value,
     pre_ctx: Option<Arc<Value>>,
+    case_session: Arc<SessionStruct>,
+    task_session: Arc<SessionStruct>,
     id: Arc<CaseIdStruct>,
 }
 
 impl CaseArgStruct {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         flow: Arc<Flow>,
         step_vec: Arc<TailDropVec<(String, Box<dyn Action>)>>,
         data: Value,
         pre_ctx: Option<Arc<Value>>,
+        task_session: Arc<SessionStruct>,
         task_id: Arc<dyn TaskId>,
         case_id: String,
         case_exec_id: Arc<String>,
@@ -77,6 +81,8 @@ impl CaseArgStruct {
             step_vec,
             data,
             pre_ctx,
+            case_session: Arc::new(SessionStruct::new()),
+            task_session,
             id,
         };
 
@@ -92,6 +98,7 @@ impl CaseArgStruct {
         render_data.insert(String::from("case"), self.data.clone());
         render_data.insert(String::from("step"), Value::Object(Map::new()));
         render_data.insert(String::from("curr"), Value::Null);
+        render_data.insert(String::from("session"), Value::Object(self.session_data()));
         if let Some(pre_ctx) = self.pre_ctx.as_ref() {
             render_data.insert(String::from("pre"), pre_ctx.as_ref().clone());
         }
@@ -115,6 +122,8 @@ impl CaseArgStruct {
             flow_ctx.get_handlebars(),
             render_ctx,
             flow_ctx.get_flow_parse(),
+            self.case_session.clone(),
+            self.task_session.clone(),
             self.id.clone(),
             step_id.to_owned(),
         )
@@ -124,6 +133,13 @@ impl CaseArgStruct {
         self.step_vec.clone()
     }
 
+    /// task session overlaid by case session
+    pub fn session_data(&self) -> Map {
+        let mut data = self.task_session.data();
+        data.extend(self.case_session.data());
+        data
+    }
+
     pub fn id(&self) -> Arc<CaseIdStruct> {
         self.id.clone()
     }
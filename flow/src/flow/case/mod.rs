@@ -1,3 +1,6 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use chrono::Utc;
 use log::{debug, info, trace, warn};
 
@@ -5,13 +8,13 @@ use chord::action::RunArg;
 use chord::case::CaseState;
 use chord::collection::TailDropVec;
 use chord::step::{StepAssess, StepState};
-use chord::value::{json, Value};
+use chord::value::{json, Map, Value};
 use res::CaseAssessStruct;
 
 use crate::flow::case::arg::CaseArgStruct;
-use crate::flow::step::arg::RunIdStruct;
+use crate::flow::step::arg::{RunArgStruct, RunIdStruct};
 use crate::flow::step::res::StepAssessStruct;
-use crate::flow::{assert, step};
+use crate::flow::{assert, assert_lua, step};
 use crate::model::app::{Context, RenderContext};
 
 pub mod arg;
@@ -45,10 +48,11 @@ pub async fn run(flow_ctx: &dyn Context, arg: CaseArgStruct) -> CaseAssessStruct
 
         let step_arg_id = step_arg.id().clone();
         let step_arg_args = step_arg.args().clone();
-        let step_arg_assert = step_arg.assert().map(|s| s.to_owned());
+        let step_arg_assert = StepAssert::new(&step_arg);
         let step_arg_catch_err = step_arg.catch_err();
 
         curr_register(&mut render_context, step_assess.state()).await;
+        session_register(&mut render_context, arg.session_data()).await;
         step_register(
             &mut render_context,
             step_assess.id().step(),
@@ -145,7 +149,7 @@ async fn step_assess_assert(
     flow_ctx: &dyn Context,
     render_context: &RenderContext,
     step_assess: StepAssessStruct,
-    assert_present: Option<String>,
+    step_assert: StepAssert,
 ) -> StepAssessStruct {
     if step_assess.state().is_fail() {
         // never reach
@@ -159,8 +163,8 @@ async fn step_assess_assert(
         state,
     } = step_assess;
 
-    if let Some(con) = assert_present {
-        if assert(flow_ctx.get_handlebars(), &render_context, con.as_str()).await {
+    if let Some(passed) = step_assert.passed(flow_ctx, render_context).await {
+        if passed {
             match state {
                 StepState::Ok(scope) => StepAssessStruct::new(id, start, end, StepState::Ok(scope)),
                 StepState::Err(e) => StepAssessStruct::new(
@@ -217,6 +221,53 @@ async fn step_assess_assert(
     }
 }
 
+/// `assert` and `assert_lua` of a step, both pass when present
+struct StepAssert {
+    condition: Option<String>,
+    lua: Option<String>,
+    dir: PathBuf,
+    timeout: Duration,
+}
+
+impl StepAssert {
+    fn new(step_arg: &RunArgStruct<'_, '_, '_, '_, '_>) -> StepAssert {
+        StepAssert {
+            condition: step_arg.assert().map(|s| s.to_owned()),
+            lua: step_arg.assert_lua().map(|s| s.to_owned()),
+            dir: step_arg.dir().to_path_buf(),
+            timeout: step_arg.timeout(),
+        }
+    }
+
+    /// none without any
+    async fn passed(&self, flow_ctx: &dyn Context, render_context: &RenderContext) -> Option<bool> {
+        if self.condition.is_none() && self.lua.is_none() {
+            return None;
+        }
+        let passed = self
+            .condition
+            .iter()
+            .all(|con| assert(flow_ctx.get_handlebars(), render_context, con.as_str()));
+        if !passed {
+            return Some(false);
+        }
+        for code in self.lua.iter() {
+            let passed = assert_lua(
+                flow_ctx.get_lua_limit(),
+                render_context,
+                code.as_str(),
+                self.dir.as_path(),
+                self.timeout,
+            )
+            .await;
+            if !passed {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+}
+
 pub async fn step_register(render_context: &mut RenderContext, sid: &str, state: &StepState) {
     match state {
         StepState::Ok(scope) => {
@@ -265,6 +316,12 @@ pub async fn curr_register(render_context: &mut RenderContext, state: &StepState
     }
 }
 
+pub async fn session_register(render_context: &mut RenderContext, session: Map) {
+    if let Value::Object(reg) = render_context.data_mut() {
+        reg["session"] = Value::Object(session);
+    }
+}
+
 pub async fn curr_reset(render_context: &mut RenderContext) {
     if let Value::Object(reg) = render_context.data_mut() {
         reg["curr"] = Value::Null;
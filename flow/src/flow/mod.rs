@@ -1,6 +1,9 @@
 use std::cell::RefCell;
+use std::path::Path;
+use std::time::Duration;
 
 use async_std::sync::Arc;
+use async_std::task::spawn_blocking;
 use async_std::task_local;
 use handlebars::Handlebars;
 use log::info;
@@ -8,6 +11,9 @@ use log::info;
 use chord::action::Factory;
 use chord::err;
 use chord::input::FlowParse;
+use chord::lua;
+use chord::lua::{rlua, Limit};
+use chord::value::Value;
 use chord::Error;
 pub use task::arg::TaskIdSimple;
 pub use task::TaskRunner;
@@ -22,11 +28,24 @@ task_local! {
     pub static CTX_ID: RefCell<String> = RefCell::new(String::new());
 }
 
+/// `lua` is the config of the `lua` action, its limits apply to `assert_lua` and `helpers.lua`,
+/// a helper call runs for `helper_timeout` seconds at most, 5 by default
 pub async fn context_create(
     action_factory: Box<dyn Factory>,
     flow_parse: Box<dyn FlowParse>,
+    lua: Option<&Value>,
 ) -> Arc<dyn Context> {
-    Arc::new(FlowContextStruct::<'_>::new(action_factory, flow_parse))
+    let lua_limit = match lua {
+        Some(c) => Limit::default().with(c),
+        None => Limit::default(),
+    };
+    let lua_timeout = lua.and_then(|c| c["helper_timeout"].as_u64()).unwrap_or(5);
+    Arc::new(FlowContextStruct::<'_>::new(
+        action_factory,
+        flow_parse,
+        lua_limit,
+        Duration::from_secs(lua_timeout),
+    ))
 }
 
 pub fn render(
@@ -41,7 +60,7 @@ pub fn render(
     };
 }
 
-pub async fn assert(
+pub fn assert(
     handlebars: &Handlebars<'_>,
     render_context: &RenderContext,
     condition: &str,
@@ -66,3 +85,57 @@ pub async fn assert(
         }
     }
 }
+
+/// `code` passes unless it is `nil` or `false`, `require` loads modules in the task `dir`,
+/// it runs on a blocking thread like the `lua` action
+pub async fn assert_lua(
+    limit: Limit,
+    render_context: &RenderContext,
+    code: &str,
+    dir: &Path,
+    timeout: Duration,
+) -> bool {
+    let data = render_context.data().clone();
+    let dir = dir.to_path_buf();
+    let lua_code = code.to_owned();
+    let result = spawn_blocking(move || {
+        let rt = lua::sandbox(dir.as_path())?;
+        lua::limit(&rt, limit, timeout);
+        rt.context(|ctx| -> Result<bool, Error> {
+            lua::context_set(ctx, &data)?;
+            let v: rlua::Value = ctx.load(lua_code.as_str()).eval()?;
+            Ok(!matches!(v, rlua::Value::Nil | rlua::Value::Boolean(false)))
+        })
+    })
+    .await;
+    match result {
+        Ok(result) => result,
+        Err(e) => {
+            info!("assert_lua failure: {} >>> {}", code, e);
+            false
+        }
+    }
+}
+
+#[test]
+fn assert_lua_test() {
+    async_std::task::block_on(async {
+        let context = RenderContext::wraps(chord::value::json!({"case": {"a": 1}})).unwrap();
+        let dir = Path::new(".");
+        let timeout = Duration::from_secs(5);
+        let limit = Limit::default();
+
+        assert!(assert_lua(limit, &context, "return case.a == 1", dir, timeout).await);
+        assert!(assert_lua(limit, &context, "return 0", dir, timeout).await);
+        assert!(!assert_lua(limit, &context, "return nil", dir, timeout).await);
+        assert!(!assert_lua(limit, &context, "return false", dir, timeout).await);
+        assert!(!assert_lua(limit, &context, "error('boom')", dir, timeout).await);
+        assert!(!assert_lua(limit, &context, "case.a = 2", dir, timeout).await);
+
+        let limit = Limit {
+            instruction: 100000,
+            ..limit
+        };
+        assert!(!assert_lua(limit, &context, "while true do end", dir, timeout).await);
+    });
+}
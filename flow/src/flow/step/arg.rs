@@ -1,11 +1,14 @@
+use std::any::Any;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use handlebars::Handlebars;
 
 use chord::action::RunId;
-use chord::action::{CreateArg, CreateId, RunArg};
+use chord::action::{CreateArg, CreateId, RunArg, Session};
 use chord::case::CaseId;
 use chord::flow::Flow;
 use chord::task::TaskId;
@@ -116,6 +119,10 @@ impl<'f, 'h, 'reg, 'r> CreateArg for CreateArgStruct<'f, 'h, 'reg, 'r> {
         flow::render(self.handlebars, self.render_context, text)
     }
 
+    fn dir(&self) -> &Path {
+        self.flow.dir()
+    }
+
     fn is_shared(&self, text: &str) -> bool {
         if let Some(_) = text.find("{{data.") {
             return false;
@@ -130,21 +137,91 @@ impl<'f, 'h, 'reg, 'r> CreateArg for CreateArgStruct<'f, 'h, 'reg, 'r> {
     }
 }
 
+pub struct SessionStruct {
+    data: Mutex<Map>,
+    resource: Mutex<HashMap<String, Arc<dyn Any + Sync + Send>>>,
+}
+
+impl SessionStruct {
+    pub fn new() -> SessionStruct {
+        SessionStruct {
+            data: Mutex::new(Map::new()),
+            resource: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn data(&self) -> Map {
+        self.data.lock().unwrap().clone()
+    }
+}
+
+impl Session for SessionStruct {
+    fn get(&self, key: &str) -> Value {
+        self.data
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .unwrap_or(Value::Null)
+    }
+
+    fn set(&self, key: &str, value: Value) {
+        self.data.lock().unwrap().insert(key.to_owned(), value);
+    }
+
+    fn update(&self, key: &str, f: &mut dyn FnMut(&mut Value)) {
+        let mut data = self.data.lock().unwrap();
+        let value = data.entry(key.to_owned()).or_insert(Value::Null);
+        f(value);
+    }
+
+    fn resource(&self, key: &str) -> Option<Arc<dyn Any + Sync + Send>> {
+        self.resource.lock().unwrap().get(key).cloned()
+    }
+
+    fn set_resource(&self, key: &str, resource: Option<Arc<dyn Any + Sync + Send>>) {
+        let mut map = self.resource.lock().unwrap();
+        match resource {
+            Some(r) => map.insert(key.to_owned(), r),
+            None => map.remove(key),
+        };
+    }
+
+    fn resource_or_insert_with(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut() -> Result<Arc<dyn Any + Sync + Send>, Error>,
+    ) -> Result<Arc<dyn Any + Sync + Send>, Error> {
+        let mut map = self.resource.lock().unwrap();
+        if let Some(r) = map.get(key) {
+            return Ok(r.clone());
+        }
+        let r = f()?;
+        map.insert(key.to_owned(), r.clone());
+        Ok(r)
+    }
+}
+
 pub struct RunArgStruct<'f, 'h, 'reg, 'r, 'p> {
     flow: &'f Flow,
     handlebars: &'h Handlebars<'reg>,
     render_context: &'r RenderContext,
     flow_parse: &'p dyn FlowParse,
+    case_session: Arc<SessionStruct>,
+    task_session: Arc<SessionStruct>,
     id: RunIdStruct,
     args: Value,
 }
 
 impl<'f, 'h, 'reg, 'r, 'p> RunArgStruct<'f, 'h, 'reg, 'r, 'p> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         flow: &'f Flow,
         handlebars: &'h Handlebars<'reg>,
         render_context: &'r RenderContext,
         flow_parse: &'p dyn FlowParse,
+        case_session: Arc<SessionStruct>,
+        task_session: Arc<SessionStruct>,
         case_id: Arc<dyn CaseId>,
         step_id: String,
     ) -> Result<RunArgStruct<'f, 'h, 'reg, 'r, 'p>, Error> {
@@ -158,6 +235,8 @@ impl<'f, 'h, 'reg, 'r, 'p> RunArgStruct<'f, 'h, 'reg, 'r, 'p> {
             handlebars,
             render_context,
             flow_parse,
+            case_session,
+            task_session,
             id,
             args: Value::Null,
         };
@@ -176,6 +255,10 @@ impl<'f, 'h, 'reg, 'r, 'p> RunArgStruct<'f, 'h, 'reg, 'r, 'p> {
         self.flow.step_assert(self.id().step())
     }
 
+    pub fn assert_lua(&self) -> Option<&str> {
+        self.flow.step_assert_lua(self.id().step())
+    }
+
     pub fn timeout(&self) -> Duration {
         self.flow.step_timeout(self.id().step())
     }
@@ -220,7 +303,32 @@ impl<'f, 'h, 'reg, 'r, 'p> RunArg for RunArgStruct<'f, 'h, 'reg, 'r, 'p> {
         &self.args
     }
 
+    fn context(&self) -> &Value {
+        self.render_context.data()
+    }
+
+    fn dir(&self) -> &Path {
+        self.flow.dir()
+    }
+
     fn timeout(&self) -> Duration {
         self.timeout()
     }
+
+    fn case_session(&self) -> &dyn Session {
+        self.case_session.as_ref()
+    }
+
+    fn task_session(&self) -> &dyn Session {
+        self.task_session.as_ref()
+    }
+
+    fn assert(&self, condition: &str, value: &Value) -> bool {
+        let mut render_context = self.render_context.clone();
+        if let Value::Object(reg) = render_context.data_mut() {
+            reg["curr"]["state"] = Value::String("Ok".to_owned());
+            reg["curr"]["value"] = value.clone();
+        }
+        flow::assert(self.handlebars, &render_context, condition)
+    }
 }
@@ -19,7 +19,7 @@ use res::TaskAssessStruct;
 
 use crate::flow::case;
 use crate::flow::case::arg::CaseArgStruct;
-use crate::flow::step::arg::CreateArgStruct;
+use crate::flow::step::arg::{CreateArgStruct, SessionStruct};
 use crate::flow::task::arg::TaskIdSimple;
 use crate::model::app::{Context, RenderContext};
 use crate::CTX_ID;
@@ -33,6 +33,7 @@ pub struct TaskRunner {
     stage_state: TaskState,
 
     pre_ctx: Option<Arc<Value>>,
+    task_session: Arc<SessionStruct>,
     #[allow(dead_code)]
     pre_assess: Option<Box<dyn CaseAssess>>,
     #[allow(dead_code)]
@@ -54,6 +55,10 @@ impl TaskRunner {
         flow: Arc<Flow>,
         id: Arc<TaskIdSimple>,
     ) -> Result<TaskRunner, Error> {
+        let flow_ctx = match flow_ctx.task_context(flow.dir())? {
+            Some(task_ctx) => task_ctx,
+            None => flow_ctx,
+        };
         let pre_step_vec = match flow.pre_step_id_vec() {
             Some(pre_ste_id_vec) => {
                 step_vec_create(
@@ -68,6 +73,7 @@ impl TaskRunner {
             None => vec![],
         };
         let pre_step_vec = Arc::new(TailDropVec::from(pre_step_vec));
+        let task_session = Arc::new(SessionStruct::new());
 
         return if pre_step_vec.is_empty() {
             let runner = TaskRunner {
@@ -78,6 +84,7 @@ impl TaskRunner {
                 stage_state: TaskState::Ok,
 
                 pre_ctx: None,
+                task_session,
                 pre_assess: None,
                 pre_step_vec: None,
 
@@ -89,7 +96,13 @@ impl TaskRunner {
             };
             Ok(runner)
         } else {
-            let pre_arg = pre_arg(flow.clone(), id.clone(), pre_step_vec.clone()).await?;
+            let pre_arg = pre_arg(
+                flow.clone(),
+                id.clone(),
+                pre_step_vec.clone(),
+                task_session.clone(),
+            )
+            .await?;
             let pre_assess = case_run(flow_ctx.as_ref(), pre_arg).await;
             let pre_ctx = pre_ctx_create(pre_assess.as_ref()).await?;
             let runner = TaskRunner {
@@ -100,6 +113,7 @@ impl TaskRunner {
                 stage_state: TaskState::Ok,
 
                 pre_ctx: Some(Arc::new(pre_ctx)),
+                task_session,
                 pre_assess: Some(pre_assess),
                 pre_step_vec: Some(pre_step_vec),
 
@@ -273,8 +287,7 @@ impl TaskRunner {
                         if let Value::Object(d) = ctx.data_mut() {
                             d.insert("case".into(), cd.clone());
                             let filter_ok =
-                                crate::flow::assert(self.flow_ctx.get_handlebars(), &ctx, filter)
-                                    .await;
+                                crate::flow::assert(self.flow_ctx.get_handlebars(), &ctx, filter);
                             if filter_ok {
                                 ccdv.push((cid, cd));
                             }
@@ -326,6 +339,7 @@ impl TaskRunner {
                     self.step_vec.clone(),
                     d,
                     self.pre_ctx.clone(),
+                    self.task_session.clone(),
                     self.id.clone(),
                     id,
                     self.case_exec_id.clone(),
@@ -340,12 +354,14 @@ async fn pre_arg(
     flow: Arc<Flow>,
     task_id: Arc<TaskIdSimple>,
     pre_action_vec: Arc<TailDropVec<(String, Box<dyn Action>)>>,
+    task_session: Arc<SessionStruct>,
 ) -> Result<CaseArgStruct, Error> {
     Ok(CaseArgStruct::new(
         flow.clone(),
         pre_action_vec,
         Value::Null,
         None,
+        task_session,
         task_id.clone(),
         "pre".into(),
         Arc::new("pre".into()),
@@ -1,10 +1,15 @@
 use std::borrow::Borrow;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 use handlebars::Handlebars;
 
 use crate::model::helper::register;
 use chord::action::Factory;
 use chord::input::FlowParse;
+use chord::lua::Limit;
+use chord::Error;
 
 pub trait Context: Sync + Send {
     fn get_handlebars(&self) -> &Handlebars;
@@ -12,25 +17,37 @@ pub trait Context: Sync + Send {
     fn get_action_factory(&self) -> &dyn Factory;
 
     fn get_flow_parse(&self) -> &dyn FlowParse;
+
+    /// the sandbox limits of lua asserts and helpers
+    fn get_lua_limit(&self) -> Limit;
+
+    /// a context of the task in `dir` with the helpers of its `helpers.lua`, none without one
+    fn task_context(&self, dir: &Path) -> Result<Option<Arc<dyn Context>>, Error>;
 }
 
 pub struct FlowContextStruct<'reg> {
     handlebars: Handlebars<'reg>,
-    action_factory: Box<dyn Factory>,
-    flow_parse: Box<dyn FlowParse>,
+    action_factory: Arc<dyn Factory>,
+    flow_parse: Arc<dyn FlowParse>,
+    lua_limit: Limit,
+    lua_timeout: Duration,
 }
 
 impl<'reg> FlowContextStruct<'reg> {
     pub fn new(
         action_factory: Box<dyn Factory>,
         flow_parse: Box<dyn FlowParse>,
+        lua_limit: Limit,
+        lua_timeout: Duration,
     ) -> FlowContextStruct<'reg> {
         let mut handlebars = Handlebars::new();
         register(&mut handlebars);
         FlowContextStruct {
             handlebars,
-            action_factory,
-            flow_parse,
+            action_factory: Arc::from(action_factory),
+            flow_parse: Arc::from(flow_parse),
+            lua_limit,
+            lua_timeout,
         }
     }
 }
@@ -47,6 +64,33 @@ impl<'reg> Context for FlowContextStruct<'reg> {
     fn get_flow_parse(&self) -> &dyn FlowParse {
         self.flow_parse.as_ref()
     }
+
+    fn get_lua_limit(&self) -> Limit {
+        self.lua_limit
+    }
+
+    fn task_context(&self, dir: &Path) -> Result<Option<Arc<dyn Context>>, Error> {
+        let helpers = dir.join("helpers.lua");
+        if !helpers.exists() {
+            return Ok(None);
+        }
+
+        let mut handlebars = Handlebars::new();
+        register(&mut handlebars);
+        crate::model::helper::lua::register(
+            &mut handlebars,
+            helpers.as_path(),
+            self.lua_limit,
+            self.lua_timeout,
+        )?;
+        Ok(Some(Arc::new(FlowContextStruct {
+            handlebars,
+            action_factory: self.action_factory.clone(),
+            flow_parse: self.flow_parse.clone(),
+            lua_limit: self.lua_limit,
+            lua_timeout: self.lua_timeout,
+        })))
+    }
 }
 
 pub type RenderContext = handlebars::Context;
@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use handlebars::{Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, ScopedJson};
+
+use chord::lua;
+use chord::lua::{rlua, Limit};
+use chord::value::Value;
+use chord::{err, Error};
+
+/// `helpers` returns a table of functions, each is a helper of its name
+///
+/// ```lua
+/// local helpers = {}
+///
+/// function helpers.sum(list)
+///     local s = 0
+///     for _, v in ipairs(list) do s = s + v end
+///     return s
+/// end
+///
+/// return helpers
+/// ```
+///
+/// `{{sum step.search.value.price}}`, `(eq (sum curr.value) 10)`
+///
+/// a call holds the rendering thread for at most `timeout`, concurrent renders call into states
+/// of their own, so globals set by a helper are not shared between them
+pub fn register(
+    handlebars: &mut Handlebars,
+    helpers: &Path,
+    limit: Limit,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let helpers = Arc::new(Helpers {
+        code: std::fs::read_to_string(helpers)?,
+        dir: helpers
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf(),
+        limit,
+        timeout,
+        idle: Mutex::new(vec![]),
+    });
+    let rt = helpers.load()?;
+    let names = rt.context(|ctx| -> Result<Vec<String>, Error> {
+        let table: rlua::Table = ctx.named_registry_value("chord_helpers")?;
+        let mut names = Vec::new();
+        for pair in table.pairs::<String, rlua::Value>() {
+            if let (name, rlua::Value::Function(_)) = pair? {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    })?;
+    helpers.idle.lock().unwrap().push(rt);
+
+    for name in names {
+        if handlebars.get_helper(name.as_str()).is_some() {
+            return Err(err!("helper", format!("helper {} exists", name)));
+        }
+        let helper = LuaHelper {
+            helpers: helpers.clone(),
+            name: name.clone(),
+        };
+        handlebars.register_helper(name.as_str(), Box::new(helper));
+    }
+    Ok(())
+}
+
+/// idle states with `helpers.lua` loaded
+struct Helpers {
+    code: String,
+    dir: PathBuf,
+    limit: Limit,
+    timeout: Duration,
+    idle: Mutex<Vec<rlua::Lua>>,
+}
+
+impl Helpers {
+    fn load(&self) -> Result<rlua::Lua, Error> {
+        let rt = lua::sandbox(self.dir.as_path())?;
+        lua::limit(&rt, self.limit, self.timeout);
+        rt.context(|ctx| -> Result<(), Error> {
+            let table: rlua::Table = ctx
+                .load(self.code.as_str())
+                .set_name("helpers")?
+                .eval()
+                .map_err(|e| err!("helper", format!("helpers.lua: {}", e)))?;
+            ctx.set_named_registry_value("chord_helpers", table)?;
+            Ok(())
+        })?;
+        Ok(rt)
+    }
+
+    fn call(&self, name: &str, params: &[&Value]) -> Result<Value, Error> {
+        let idle = self.idle.lock().unwrap().pop();
+        let rt = match idle {
+            Some(rt) => rt,
+            None => self.load()?,
+        };
+        lua::limit(&rt, self.limit, self.timeout);
+
+        let value = rt.context(|ctx| -> Result<Value, Error> {
+            let helpers: rlua::Table = ctx.named_registry_value("chord_helpers")?;
+            let f: rlua::Function = helpers.get(name)?;
+            let mut args = Vec::with_capacity(params.len());
+            for p in params {
+                args.push(lua::from_value(ctx, p)?);
+            }
+            let v: rlua::Value = f.call(rlua::MultiValue::from_vec(args))?;
+            lua::to_value(&v)
+        });
+        self.idle.lock().unwrap().push(rt);
+        value
+    }
+}
+
+struct LuaHelper {
+    helpers: Arc<Helpers>,
+    name: String,
+}
+
+impl HelperDef for LuaHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<Option<ScopedJson<'reg, 'rc>>, RenderError> {
+        let params: Vec<&Value> = h.params().iter().map(|p| p.value()).collect();
+        self.helpers
+            .call(self.name.as_str(), &params)
+            .map(|v| Some(ScopedJson::Derived(v)))
+            .map_err(|e| RenderError::new(format!("helper \"{}\": {}", self.name, e.message())))
+    }
+}
+
+#[test]
+fn register_test() {
+    let dir = std::env::temp_dir().join(format!("chord_helper_lua_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let helpers = dir.join("helpers.lua");
+    std::fs::write(
+        &helpers,
+        r#"
+        local helpers = {}
+        function helpers.sum(list)
+            local s = 0
+            for _, v in ipairs(list) do s = s + v end
+            return s
+        end
+        function helpers.spin()
+            while true do end
+        end
+        return helpers
+        "#,
+    )
+    .unwrap();
+
+    let mut handlebars = Handlebars::new();
+    let limit = Limit {
+        instruction: 100000,
+        ..Limit::default()
+    };
+    register(&mut handlebars, &helpers, limit, Duration::from_secs(5)).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let data = chord::value::json!({"list": [1, 2, 3]});
+    assert_eq!(
+        "6",
+        handlebars.render_template("{{sum list}}", &data).unwrap()
+    );
+    assert_eq!(
+        "yes",
+        handlebars
+            .render_template("{{#if (eq (sum list) 6)}}yes{{/if}}", &data)
+            .unwrap()
+    );
+    let e = handlebars.render_template("{{spin}}", &data).unwrap_err();
+    assert!(e.to_string().contains("instruction limit exceeded"));
+    assert_eq!(
+        "6",
+        handlebars.render_template("{{sum list}}", &data).unwrap()
+    );
+}
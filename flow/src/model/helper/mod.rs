@@ -4,6 +4,7 @@ use handlebars::{Context, Handlebars, Helper, HelperDef, RenderContext, RenderEr
 
 mod array;
 mod boolean;
+pub mod lua;
 mod number;
 mod string;
 
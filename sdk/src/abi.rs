@@ -0,0 +1,143 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+
+use chord::value::{from_str, json, Value};
+use chord::{err, Error};
+
+/// bumped on any change of the structs below, a host loads only plugins of its own version
+pub const ABI_VERSION: u32 = 1;
+
+/// the symbol a plugin exports, `extern "C" fn() -> *const Plugin`
+pub const PLUGIN_SYMBOL: &[u8] = b"chord_plugin";
+
+pub const SESSION_CASE: u32 = 0;
+pub const SESSION_TASK: u32 = 1;
+
+/// the strings are nul terminated utf-8 json, a string is freed by the side that made it:
+/// the host frees the strings of these functions with `str_free`,
+/// the plugin frees the strings of the host callbacks with the `str_free` of the arg.
+///
+/// `factory_create` and `action_create` return null on success, else an error
+/// `{"code": ..., "message": ...}`, `action_run` returns `{"value": ...}` or `{"error": ...}`
+#[repr(C)]
+pub struct Plugin {
+    pub abi_version: u32,
+    /// the action name, a static string
+    pub name: *const c_char,
+    pub factory_create:
+        unsafe extern "C" fn(config: *const c_char, factory: *mut *mut c_void) -> *mut c_char,
+    pub factory_destroy: unsafe extern "C" fn(factory: *mut c_void),
+    pub action_create: unsafe extern "C" fn(
+        factory: *const c_void,
+        arg: *const CreateArg,
+        action: *mut *mut c_void,
+    ) -> *mut c_char,
+    pub action_run: unsafe extern "C" fn(action: *const c_void, arg: *const RunArg) -> *mut c_char,
+    pub action_free: unsafe extern "C" fn(action: *mut c_void),
+    pub str_free: unsafe extern "C" fn(s: *mut c_char),
+}
+
+// only static data and functions
+unsafe impl Sync for Plugin {}
+
+/// `arg` is `{"id": ..., "step": ..., "task": {"task": ..., "exec_id": ...}, "action": ..., "args": ..., "dir": ...}`,
+/// `render_str` returns `{"value": ...}` or `{"error": ...}`
+#[repr(C)]
+pub struct CreateArg {
+    pub arg: *const c_char,
+    pub host: *const c_void,
+    pub render_str: unsafe extern "C" fn(host: *const c_void, text: *const c_char) -> *mut c_char,
+    pub is_shared: unsafe extern "C" fn(host: *const c_void, text: *const c_char) -> bool,
+    pub str_free: unsafe extern "C" fn(s: *mut c_char),
+}
+
+// the host callbacks take a `Sync` arg
+unsafe impl Sync for CreateArg {}
+unsafe impl Send for CreateArg {}
+
+/// `arg` is `{"id": ..., "step": ..., "case": {"case": ..., "exec_id": ...}, "task": {...},
+/// "args": ..., "context": ..., "dir": ..., "timeout": <millis>}`,
+/// `session_get` returns the value, `scope` is `SESSION_CASE` or `SESSION_TASK`
+#[repr(C)]
+pub struct RunArg {
+    pub arg: *const c_char,
+    pub host: *const c_void,
+    pub assert: unsafe extern "C" fn(
+        host: *const c_void,
+        condition: *const c_char,
+        value: *const c_char,
+    ) -> bool,
+    pub session_get:
+        unsafe extern "C" fn(host: *const c_void, scope: u32, key: *const c_char) -> *mut c_char,
+    pub session_set: unsafe extern "C" fn(
+        host: *const c_void,
+        scope: u32,
+        key: *const c_char,
+        value: *const c_char,
+    ),
+    pub str_free: unsafe extern "C" fn(s: *mut c_char),
+}
+
+unsafe impl Sync for RunArg {}
+unsafe impl Send for RunArg {}
+
+/// a string for the other side, nul bytes are dropped
+pub fn str_into(s: String) -> *mut c_char {
+    let s = CString::new(s).unwrap_or_else(|e| {
+        let mut bytes = e.into_vec();
+        bytes.retain(|b| *b != 0);
+        CString::new(bytes).unwrap_or_default()
+    });
+    s.into_raw()
+}
+
+/// frees a string of `str_into`
+///
+/// # Safety
+/// `s` is null or from `str_into` of the same library
+pub unsafe extern "C" fn str_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// # Safety
+/// `s` is null or nul terminated
+pub unsafe fn str_from<'a>(s: *const c_char) -> Result<&'a str, Error> {
+    if s.is_null() {
+        return Err(err!("abi", "null string"));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .or(Err(err!("abi", "invalid utf-8")))
+}
+
+pub fn error_to_value(e: &Error) -> Value {
+    json!({
+        "code": e.code(),
+        "message": e.message()
+    })
+}
+
+pub fn error_from_value(v: &Value) -> Error {
+    err!(
+        v["code"].as_str().unwrap_or("plugin"),
+        v["message"].as_str().unwrap_or_default()
+    )
+}
+
+pub fn result_to_str(r: Result<Value, Error>) -> *mut c_char {
+    let v = match r {
+        Ok(v) => json!({ "value": v }),
+        Err(e) => json!({ "error": error_to_value(&e) }),
+    };
+    str_into(v.to_string())
+}
+
+pub fn result_from_str(s: &str) -> Result<Value, Error> {
+    let mut v: Value = from_str(s).or(Err(err!("abi", "invalid result")))?;
+    if v["error"].is_object() {
+        return Err(error_from_value(&v["error"]));
+    }
+    Ok(v["value"].take())
+}
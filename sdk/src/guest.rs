@@ -0,0 +1,421 @@
+//! the plugin side of the abi, called by the functions of `export_plugin!`
+
+use std::any::Any;
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::os::raw::{c_char, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chord::action::{Action, CreateArg, CreateId, Factory, RunArg, RunId, Session};
+use chord::case::CaseId;
+use chord::task::TaskId;
+use chord::value::{from_str, to_string, Value};
+use chord::{err, Error};
+
+use crate::abi;
+
+/// a panic must not unwind into the host
+fn guard<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(r) => r,
+        Err(p) => {
+            let message = p
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| p.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(err!("panic", message))
+        }
+    }
+}
+
+fn error_into(e: Error) -> *mut c_char {
+    abi::str_into(abi::error_to_value(&e).to_string())
+}
+
+/// # Safety
+/// `config` is nul terminated json, `factory` is writable
+pub unsafe fn factory_create<F, N, Fut>(
+    config: *const c_char,
+    factory: *mut *mut c_void,
+    new: N,
+) -> *mut c_char
+where
+    F: Factory + 'static,
+    N: FnOnce(Option<Value>) -> Fut,
+    Fut: Future<Output = Result<F, Error>>,
+{
+    let created = guard(|| {
+        let config: Value = from_str(abi::str_from(config)?)?;
+        let config = if config.is_null() { None } else { Some(config) };
+        let f = async_std::task::block_on(new(config))?;
+        let f: Box<dyn Factory> = Box::new(f);
+        Ok(Box::into_raw(Box::new(f)) as *mut c_void)
+    });
+    match created {
+        Ok(f) => {
+            *factory = f;
+            std::ptr::null_mut()
+        }
+        Err(e) => error_into(e),
+    }
+}
+
+/// # Safety
+/// `factory` is from `factory_create`
+pub unsafe extern "C" fn factory_destroy(factory: *mut c_void) {
+    if !factory.is_null() {
+        let _ = guard(|| {
+            drop(Box::from_raw(factory as *mut Box<dyn Factory>));
+            Ok(())
+        });
+    }
+}
+
+/// # Safety
+/// `factory` is from `factory_create`, `arg` lives through the call
+pub unsafe extern "C" fn action_create(
+    factory: *const c_void,
+    arg: *const abi::CreateArg,
+    action: *mut *mut c_void,
+) -> *mut c_char {
+    let created = guard(|| {
+        let factory = &*(factory as *const Box<dyn Factory>);
+        let arg = GuestCreateArg::new(&*arg)?;
+        let a = async_std::task::block_on(factory.create(&arg))?;
+        Ok(Box::into_raw(Box::new(a)) as *mut c_void)
+    });
+    match created {
+        Ok(a) => {
+            *action = a;
+            std::ptr::null_mut()
+        }
+        Err(e) => error_into(e),
+    }
+}
+
+/// # Safety
+/// `action` is from `action_create`, `arg` lives through the call
+pub unsafe extern "C" fn action_run(action: *const c_void, arg: *const abi::RunArg) -> *mut c_char {
+    let value = guard(|| {
+        let action = &*(action as *const Box<dyn Action>);
+        let arg = GuestRunArg::new(&*arg)?;
+        let scope = async_std::task::block_on(action.run(&arg))?;
+        Ok(scope.as_value().clone())
+    });
+    abi::result_to_str(value)
+}
+
+/// # Safety
+/// `action` is from `action_create`
+pub unsafe extern "C" fn action_free(action: *mut c_void) {
+    if !action.is_null() {
+        let _ = guard(|| {
+            drop(Box::from_raw(action as *mut Box<dyn Action>));
+            Ok(())
+        });
+    }
+}
+
+struct GuestTaskId {
+    task: String,
+    exec_id: String,
+}
+
+impl GuestTaskId {
+    fn new(v: &Value) -> GuestTaskId {
+        GuestTaskId {
+            task: v["task"].as_str().unwrap_or_default().to_owned(),
+            exec_id: v["exec_id"].as_str().unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+impl TaskId for GuestTaskId {
+    fn task(&self) -> &str {
+        self.task.as_str()
+    }
+
+    fn exec_id(&self) -> &str {
+        self.exec_id.as_str()
+    }
+}
+
+impl Display for GuestTaskId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.exec_id, self.task)
+    }
+}
+
+struct GuestCaseId {
+    case: String,
+    exec_id: String,
+    task_id: GuestTaskId,
+}
+
+impl CaseId for GuestCaseId {
+    fn case(&self) -> &str {
+        self.case.as_str()
+    }
+
+    fn exec_id(&self) -> &str {
+        self.exec_id.as_str()
+    }
+
+    fn task_id(&self) -> &dyn TaskId {
+        &self.task_id
+    }
+}
+
+impl Display for GuestCaseId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}-{}", self.task_id, self.exec_id, self.case)
+    }
+}
+
+/// the ids keep the host display
+struct GuestId<T> {
+    id: String,
+    step: String,
+    parent: T,
+}
+
+impl<T> Display for GuestId<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.id.as_str())
+    }
+}
+
+impl CreateId for GuestId<GuestTaskId> {
+    fn step(&self) -> &str {
+        self.step.as_str()
+    }
+
+    fn task_id(&self) -> &dyn TaskId {
+        &self.parent
+    }
+}
+
+impl RunId for GuestId<GuestCaseId> {
+    fn step(&self) -> &str {
+        self.step.as_str()
+    }
+
+    fn case_id(&self) -> &dyn CaseId {
+        &self.parent
+    }
+}
+
+struct GuestCreateArg<'a> {
+    raw: &'a abi::CreateArg,
+    id: GuestId<GuestTaskId>,
+    action: String,
+    args: Value,
+    dir: PathBuf,
+}
+
+impl<'a> GuestCreateArg<'a> {
+    unsafe fn new(raw: &'a abi::CreateArg) -> Result<GuestCreateArg<'a>, Error> {
+        let mut arg: Value = from_str(abi::str_from(raw.arg)?)?;
+        Ok(GuestCreateArg {
+            raw,
+            id: GuestId {
+                id: arg["id"].as_str().unwrap_or_default().to_owned(),
+                step: arg["step"].as_str().unwrap_or_default().to_owned(),
+                parent: GuestTaskId::new(&arg["task"]),
+            },
+            action: arg["action"].as_str().unwrap_or_default().to_owned(),
+            args: arg["args"].take(),
+            dir: PathBuf::from(arg["dir"].as_str().unwrap_or_default()),
+        })
+    }
+}
+
+/// takes a string of a host callback
+unsafe fn host_str(
+    s: *mut c_char,
+    free: unsafe extern "C" fn(*mut c_char),
+) -> Result<String, Error> {
+    let owned = abi::str_from(s).map(str::to_owned);
+    free(s);
+    owned
+}
+
+fn cstring(s: &str) -> Result<CString, Error> {
+    CString::new(s).or(Err(err!("abi", "nul in string")))
+}
+
+impl CreateArg for GuestCreateArg<'_> {
+    fn id(&self) -> &dyn CreateId {
+        &self.id
+    }
+
+    fn action(&self) -> &str {
+        self.action.as_str()
+    }
+
+    fn args(&self) -> &Value {
+        &self.args
+    }
+
+    fn render_str(&self, text: &str) -> Result<String, Error> {
+        let text = cstring(text)?;
+        let rendered = unsafe {
+            host_str(
+                (self.raw.render_str)(self.raw.host, text.as_ptr()),
+                self.raw.str_free,
+            )?
+        };
+        match abi::result_from_str(rendered.as_str())? {
+            Value::String(s) => Ok(s),
+            _ => Err(err!("abi", "invalid render result")),
+        }
+    }
+
+    fn dir(&self) -> &Path {
+        self.dir.as_path()
+    }
+
+    fn is_shared(&self, text: &str) -> bool {
+        match cstring(text) {
+            Ok(text) => unsafe { (self.raw.is_shared)(self.raw.host, text.as_ptr()) },
+            Err(_) => false,
+        }
+    }
+}
+
+struct GuestRunArg<'a> {
+    id: GuestId<GuestCaseId>,
+    args: Value,
+    context: Value,
+    dir: PathBuf,
+    timeout: Duration,
+    case_session: GuestSession<'a>,
+    task_session: GuestSession<'a>,
+}
+
+impl<'a> GuestRunArg<'a> {
+    unsafe fn new(raw: &'a abi::RunArg) -> Result<GuestRunArg<'a>, Error> {
+        let mut arg: Value = from_str(abi::str_from(raw.arg)?)?;
+        Ok(GuestRunArg {
+            id: GuestId {
+                id: arg["id"].as_str().unwrap_or_default().to_owned(),
+                step: arg["step"].as_str().unwrap_or_default().to_owned(),
+                parent: GuestCaseId {
+                    case: arg["case"]["case"].as_str().unwrap_or_default().to_owned(),
+                    exec_id: arg["case"]["exec_id"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_owned(),
+                    task_id: GuestTaskId::new(&arg["task"]),
+                },
+            },
+            args: arg["args"].take(),
+            context: arg["context"].take(),
+            dir: PathBuf::from(arg["dir"].as_str().unwrap_or_default()),
+            timeout: Duration::from_millis(arg["timeout"].as_u64().unwrap_or_default()),
+            case_session: GuestSession {
+                raw,
+                scope: abi::SESSION_CASE,
+            },
+            task_session: GuestSession {
+                raw,
+                scope: abi::SESSION_TASK,
+            },
+        })
+    }
+}
+
+impl RunArg for GuestRunArg<'_> {
+    fn id(&self) -> &dyn RunId {
+        &self.id
+    }
+
+    fn args(&self) -> &Value {
+        &self.args
+    }
+
+    fn context(&self) -> &Value {
+        &self.context
+    }
+
+    fn dir(&self) -> &Path {
+        self.dir.as_path()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn case_session(&self) -> &dyn Session {
+        &self.case_session
+    }
+
+    fn task_session(&self) -> &dyn Session {
+        &self.task_session
+    }
+
+    fn assert(&self, condition: &str, value: &Value) -> bool {
+        let raw = self.case_session.raw;
+        let (condition, value) = match (cstring(condition), cstring(value.to_string().as_str())) {
+            (Ok(c), Ok(v)) => (c, v),
+            _ => return false,
+        };
+        unsafe { (raw.assert)(raw.host, condition.as_ptr(), value.as_ptr()) }
+    }
+}
+
+/// values go through the host, resources can not cross the abi and stay in the plugin
+struct GuestSession<'a> {
+    raw: &'a abi::RunArg,
+    scope: u32,
+}
+
+impl Session for GuestSession<'_> {
+    fn get(&self, key: &str) -> Value {
+        let value = cstring(key).and_then(|key| unsafe {
+            host_str(
+                (self.raw.session_get)(self.raw.host, self.scope, key.as_ptr()),
+                self.raw.str_free,
+            )
+        });
+        value
+            .ok()
+            .and_then(|v| from_str(v.as_str()).ok())
+            .unwrap_or(Value::Null)
+    }
+
+    fn set(&self, key: &str, value: Value) {
+        let value = to_string(&value).unwrap_or_default();
+        if let (Ok(key), Ok(value)) = (cstring(key), cstring(value.as_str())) {
+            unsafe {
+                (self.raw.session_set)(self.raw.host, self.scope, key.as_ptr(), value.as_ptr())
+            }
+        }
+    }
+
+    /// not atomic across the abi
+    fn update(&self, key: &str, f: &mut dyn FnMut(&mut Value)) {
+        let mut value = self.get(key);
+        f(&mut value);
+        self.set(key, value);
+    }
+
+    fn resource(&self, _: &str) -> Option<Arc<dyn Any + Sync + Send>> {
+        None
+    }
+
+    fn set_resource(&self, _: &str, _: Option<Arc<dyn Any + Sync + Send>>) {}
+
+    fn resource_or_insert_with(
+        &self,
+        _: &str,
+        f: &mut dyn FnMut() -> Result<Arc<dyn Any + Sync + Send>, Error>,
+    ) -> Result<Arc<dyn Any + Sync + Send>, Error> {
+        f()
+    }
+}
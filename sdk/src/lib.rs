@@ -0,0 +1,184 @@
+//! native action plugins, loaded by the `plugin` action from its `dir`
+//!
+//! ```ignore
+//! use chord_sdk::export_plugin;
+//! use chord_sdk::prelude::*;
+//!
+//! pub struct HelloFactory {}
+//!
+//! impl HelloFactory {
+//!     pub async fn new(_: Option<Value>) -> Result<HelloFactory, Error> {
+//!         Ok(HelloFactory {})
+//!     }
+//! }
+//!
+//! // impl Factory for HelloFactory, impl Action for Hello
+//!
+//! export_plugin!("hello", HelloFactory::new);
+//! ```
+//!
+//! the crate is a `cdylib`, only the c abi of `abi` crosses the library,
+//! a plugin may be built by another compiler than the host
+
+pub mod abi;
+#[doc(hidden)]
+pub mod guest;
+
+pub use chord;
+
+pub mod prelude {
+    pub use chord::action::prelude::*;
+}
+
+/// exports `chord_plugin`, an action `$name` made by `$new`,
+/// an `async fn(Option<Value>) -> Result<impl Factory, Error>` like the factories of the host
+#[macro_export]
+macro_rules! export_plugin {
+    ($name:expr, $new:path) => {
+        unsafe extern "C" fn __chord_factory_create(
+            config: *const ::std::os::raw::c_char,
+            factory: *mut *mut ::std::os::raw::c_void,
+        ) -> *mut ::std::os::raw::c_char {
+            $crate::guest::factory_create(config, factory, $new)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn chord_plugin() -> *const $crate::abi::Plugin {
+            static PLUGIN: $crate::abi::Plugin = $crate::abi::Plugin {
+                abi_version: $crate::abi::ABI_VERSION,
+                name: concat!($name, "\0").as_ptr() as *const ::std::os::raw::c_char,
+                factory_create: __chord_factory_create,
+                factory_destroy: $crate::guest::factory_destroy,
+                action_create: $crate::guest::action_create,
+                action_run: $crate::guest::action_run,
+                action_free: $crate::guest::action_free,
+                str_free: $crate::abi::str_free,
+            };
+            &PLUGIN
+        }
+    };
+}
+
+#[test]
+fn plugin_test() {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_void};
+
+    use crate::abi;
+    use crate::prelude::*;
+
+    struct EchoFactory {
+        prefix: String,
+    }
+
+    impl EchoFactory {
+        async fn new(config: Option<Value>) -> Result<EchoFactory, Error> {
+            let prefix = config.map_or(Value::Null, |c| c["prefix"].clone());
+            Ok(EchoFactory {
+                prefix: prefix.as_str().unwrap_or_default().to_owned(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Factory for EchoFactory {
+        async fn create(&self, arg: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+            let name = arg.render_str(arg.args()["name"].as_str().unwrap_or_default())?;
+            Ok(Box::new(Echo {
+                name: format!("{}{}", self.prefix, name),
+            }))
+        }
+    }
+
+    struct Echo {
+        name: String,
+    }
+
+    #[async_trait]
+    impl Action for Echo {
+        async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+            if arg.args()["fail"].as_bool().unwrap_or(false) {
+                return Err(err!("echo", "fail"));
+            }
+            arg.case_session().set("count", json!(1));
+            Ok(Box::new(json!({
+                "name": self.name,
+                "step": arg.id().step(),
+                "count": arg.case_session().get("count")
+            })))
+        }
+    }
+
+    export_plugin!("echo", EchoFactory::new);
+
+    unsafe extern "C" fn render_str(_: *const c_void, text: *const c_char) -> *mut c_char {
+        let text = CStr::from_ptr(text).to_str().unwrap();
+        abi::result_to_str(Ok(json!(text.replace("{{case.name}}", "foo"))))
+    }
+
+    unsafe extern "C" fn is_shared(_: *const c_void, _: *const c_char) -> bool {
+        false
+    }
+
+    unsafe extern "C" fn assert(_: *const c_void, _: *const c_char, _: *const c_char) -> bool {
+        true
+    }
+
+    unsafe extern "C" fn session_get(_: *const c_void, _: u32, _: *const c_char) -> *mut c_char {
+        abi::str_into("1".into())
+    }
+
+    unsafe extern "C" fn session_set(_: *const c_void, _: u32, _: *const c_char, _: *const c_char) {
+    }
+
+    let plugin = unsafe { &*chord_plugin() };
+    assert_eq!(abi::ABI_VERSION, plugin.abi_version);
+    assert_eq!("echo", unsafe { abi::str_from(plugin.name) }.unwrap());
+
+    let config = CString::new(r#"{"prefix": "hi "}"#).unwrap();
+    let mut factory = std::ptr::null_mut();
+    let e = unsafe { (plugin.factory_create)(config.as_ptr(), &mut factory) };
+    assert!(e.is_null());
+
+    let create = CString::new(
+        r#"{"id": "t-s", "step": "s", "task": {"task": "t"}, "args": {"name": "{{case.name}}"}}"#,
+    )
+    .unwrap();
+    let create_arg = abi::CreateArg {
+        arg: create.as_ptr(),
+        host: std::ptr::null(),
+        render_str,
+        is_shared,
+        str_free: abi::str_free,
+    };
+    let mut action = std::ptr::null_mut();
+    let e = unsafe { (plugin.action_create)(factory, &create_arg, &mut action) };
+    assert!(e.is_null());
+
+    for (args, expect) in [
+        ("{}", Ok(json!({"name": "hi foo", "step": "s", "count": 1}))),
+        (r#"{"fail": true}"#, Err("echo")),
+    ] {
+        let run = CString::new(format!(r#"{{"step": "s", "args": {}}}"#, args)).unwrap();
+        let run_arg = abi::RunArg {
+            arg: run.as_ptr(),
+            host: std::ptr::null(),
+            assert,
+            session_get,
+            session_set,
+            str_free: abi::str_free,
+        };
+        let s = unsafe { (plugin.action_run)(action, &run_arg) };
+        let r = abi::result_from_str(unsafe { abi::str_from(s) }.unwrap());
+        unsafe { (plugin.str_free)(s) };
+        assert_eq!(
+            expect.map_err(str::to_owned),
+            r.map_err(|e| e.code().to_owned())
+        );
+    }
+
+    unsafe {
+        (plugin.action_free)(action);
+        (plugin.factory_destroy)(factory);
+    }
+}
@@ -109,7 +109,7 @@ async fn task_run0<P: AsRef<Path>>(
     let flow_path = task_path.clone().join("flow.yml");
 
     let flow = chord_input::load::flow::yml::load(&flow_path)?;
-    let flow = Flow::new(flow)?;
+    let flow = Flow::new(flow, task_path.as_ref())?;
 
     //read
     let data_file_path = task_path.clone().join("case.csv");
@@ -66,6 +66,7 @@ impl CtlImpl {
             flow_ctx: chord_flow::context_create(
                 Box::new(FactoryComposite::new(config.action().map(|c| c.clone())).await?),
                 Box::new(YmlFlowParser::new()),
+                config.action().map(|c| &c["lua"]),
             )
             .await,
             config,
@@ -1,31 +1,58 @@
-use chord::step::StepValue;
-use chord::value::{from_str, Map, Value};
-use chord::Error;
-use lazy_static::lazy_static;
-use std::ops::DerefMut;
-use std::sync::Mutex;
+use chord_sdk::export_plugin;
+use chord_sdk::prelude::*;
 
-lazy_static! {
-    static ref CONTEXT: Mutex<Map> = Mutex::new(Map::new());
+/// ```yaml
+/// hello:
+///     greeting: hi
+/// ```
+pub struct HelloFactory {
+    greeting: String,
 }
 
-#[no_mangle]
-pub fn init(id: &str, config: &str) -> Result<(), Error> {
-    let config: Map = from_str(config)?;
-    println!("fdylib create {}, {:?}", id, config);
-    let mut ctx = CONTEXT.lock().unwrap();
-    let ctx = ctx.deref_mut();
-    ctx.insert("create".into(), "1".into());
+impl HelloFactory {
+    pub async fn new(config: Option<Value>) -> Result<HelloFactory, Error> {
+        let greeting = config
+            .as_ref()
+            .and_then(|c| c["greeting"].as_str())
+            .unwrap_or("hello")
+            .to_owned();
+        Ok(HelloFactory { greeting })
+    }
+}
 
-    Ok(())
+#[async_trait]
+impl Factory for HelloFactory {
+    async fn create(&self, _: &dyn CreateArg) -> Result<Box<dyn Action>, Error> {
+        Ok(Box::new(Hello {
+            greeting: self.greeting.clone(),
+        }))
+    }
 }
 
-#[no_mangle]
-pub fn run(id: &str, config: &str) -> StepValue {
-    let config: Map = from_str(config)?;
-    println!("fdylib run {}, {:?}", id, config);
-    let mut ctx = CONTEXT.lock().unwrap();
-    let ctx = ctx.deref_mut();
-    ctx.insert("run".into(), "1".into());
-    Ok(Value::String(format!("fdylib run {}", id)))
+struct Hello {
+    greeting: String,
 }
+
+/// ```yaml
+/// name: "{{case.name}}"
+/// ```
+#[async_trait]
+impl Action for Hello {
+    async fn run(&self, arg: &dyn RunArg) -> Result<Box<dyn Scope>, Error> {
+        let name = arg.args()["name"]
+            .as_str()
+            .ok_or(err!("100", "missing name"))?;
+
+        let mut count = 0;
+        arg.case_session().update("hello", &mut |v| {
+            count = v.as_u64().unwrap_or(0) + 1;
+            *v = Value::from(count);
+        });
+        Ok(Box::new(json!({
+            "message": format!("{} {}", self.greeting, name),
+            "count": count
+        })))
+    }
+}
+
+export_plugin!("hello", HelloFactory::new);
@@ -0,0 +1,38 @@
+use serde_json::{json, Value};
+
+#[link(wasm_import_module = "chord")]
+extern "C" {
+    fn log(level: i32, ptr: *const u8, len: i32);
+}
+
+#[no_mangle]
+pub extern "C" fn chord_alloc(len: i32) -> *mut u8 {
+    // left to the instance, it is dropped after the run
+    let mut buf = Vec::with_capacity(len as usize);
+    let ptr = buf.as_mut_ptr();
+    std::mem::forget(buf);
+    ptr
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chord_run(ptr: *const u8, len: i32) -> i64 {
+    let input = std::slice::from_raw_parts(ptr, len as usize);
+    let output = match serde_json::from_slice::<Value>(input) {
+        Ok(input) => run(&input),
+        Err(e) => json!({"error": {"code": "input", "message": e.to_string()}}),
+    };
+    let output = output.to_string().into_bytes();
+    let packed = (output.as_ptr() as i64) << 32 | output.len() as i64;
+    std::mem::forget(output);
+    packed
+}
+
+fn run(input: &Value) -> Value {
+    let message = format!("hello run {}", input["id"]);
+    unsafe { log(3, message.as_ptr(), message.len() as i32) };
+
+    match input["args"]["name"].as_str() {
+        Some(name) => json!({ "value": format!("hello {}", name) }),
+        None => json!({"error": {"code": "name", "message": "missing name"}}),
+    }
+}
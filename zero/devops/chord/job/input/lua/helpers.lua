@@ -0,0 +1,8 @@
+local util = require("util")
+local helpers = {}
+
+function helpers.sum(a, b)
+    return util.sum(a, b)
+end
+
+return helpers
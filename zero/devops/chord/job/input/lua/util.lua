@@ -0,0 +1,13 @@
+local util = {}
+
+function util.sum(a, b)
+    return tonumber(a) + tonumber(b)
+end
+
+function util.append(list, v)
+    list = list or {}
+    table.insert(list, v)
+    return list
+end
+
+return util